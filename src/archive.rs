@@ -0,0 +1,158 @@
+use crate::logger::*;
+use crate::parse::*;
+use crate::storage::*;
+
+use clap::crate_version;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Error, ErrorKind, Write};
+use std::path::Path;
+
+const ARCHIVE_FORMAT: &str = "beast-archive";
+const ARCHIVE_VERSION: u32 = 1;
+
+/// First line of an archive, all following lines contain one entry each (JSON Lines).
+#[derive(Serialize, Deserialize, Debug)]
+struct ArchiveHeader {
+    format: String,
+    version: u32,
+    beast_version: String,
+    entry_count: usize,
+}
+
+pub fn export_archive(entries: &[DataBaseEntry], file_path: &Path) {
+    let f = File::create(file_path).unwrap_or_else(|e| {
+        error_and_exit(
+            &format!("Could not create file {}", file_path.to_string_lossy()),
+            &e,
+        )
+    });
+    let mut writer = BufWriter::new(f);
+
+    let header = ArchiveHeader {
+        format: ARCHIVE_FORMAT.to_string(),
+        version: ARCHIVE_VERSION,
+        beast_version: crate_version!().to_string(),
+        entry_count: entries.len(),
+    };
+
+    let write_result = write_json_line(&mut writer, &header).and_then(|_| {
+        entries
+            .iter()
+            .try_for_each(|entry| write_json_line(&mut writer, entry))
+    });
+    if let Err(e) = write_result.and_then(|_| writer.flush()) {
+        error_and_exit(
+            &format!("Could not write to file {}", file_path.to_string_lossy()),
+            &e,
+        );
+    }
+}
+
+pub fn import_archive(file_path: &Path) -> Vec<DataBaseEntry> {
+    let f = File::open(file_path).unwrap_or_else(|e| {
+        error_and_exit(
+            &format!("Could not open file {}", file_path.to_string_lossy()),
+            &e,
+        )
+    });
+    let mut lines = BufReader::new(f).lines();
+
+    let header: ArchiveHeader = match lines.next() {
+        Some(Ok(line)) => serde_json::from_str(&line)
+            .unwrap_or_else(|e| error_and_exit("Archive header has invalid format", &e)),
+        Some(Err(e)) => error_and_exit("Could not read archive", &e),
+        None => error_and_exit(
+            "Could not read archive",
+            &Error::new(ErrorKind::UnexpectedEof, "empty file"),
+        ),
+    };
+    if header.format != ARCHIVE_FORMAT || header.version > ARCHIVE_VERSION {
+        error_and_exit(
+            "Unsupported archive format",
+            &Error::new(
+                ErrorKind::InvalidData,
+                format!("{} version {}", header.format, header.version),
+            ),
+        );
+    }
+
+    let mut entries = Vec::with_capacity(header.entry_count);
+    for (line_index, line) in lines.enumerate() {
+        let line = line.unwrap_or_else(|e| error_and_exit("Could not read archive", &e));
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).unwrap_or_else(|e| {
+            error_and_exit(
+                &format!(
+                    "Archive entry in line {} has invalid format",
+                    line_index + 2
+                ),
+                &e,
+            )
+        });
+        entries.push(entry);
+    }
+    entries
+}
+
+/// Removes all entries already present in the storage.
+pub fn without_duplicates(
+    storage: &dyn Storage,
+    entries: Vec<DataBaseEntry>,
+) -> Vec<DataBaseEntry> {
+    let exe_names: HashSet<&str> = entries
+        .iter()
+        .map(|entry| entry.exe_name.as_str())
+        .collect();
+
+    let mut existing_keys = HashSet::new();
+    for exe_name in exe_names {
+        let exe_filter = EntryFilter::ExeName(format!("^{}$", regex::escape(exe_name)));
+        for existing_entry in storage.fetch(&exe_filter, None) {
+            existing_keys.insert(entry_key(&existing_entry));
+        }
+    }
+
+    entries
+        .into_iter()
+        .filter(|entry| existing_keys.insert(entry_key(entry)))
+        .collect()
+}
+
+fn write_json_line<W: Write, T: Serialize>(writer: &mut W, value: &T) -> std::io::Result<()> {
+    serde_json::to_writer(&mut *writer, value)?;
+    writer.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{test_entry, MemoryStorage};
+
+    #[test]
+    fn test_archive_roundtrip_without_duplicates() {
+        let archive_path =
+            std::env::temp_dir().join(format!("beast_archive_test_{}.jsonl", std::process::id()));
+        let mut other_run = test_entry("simple_benchmark", "v2");
+        other_run.results.context.date = "2021-02-01T00:00:00+00:00".to_string();
+        export_archive(
+            &[test_entry("simple_benchmark", "v1"), other_run],
+            &archive_path,
+        );
+
+        let storage = MemoryStorage::default();
+        storage.push(
+            &[test_entry("simple_benchmark", "v1")],
+            DuplicatePolicy::Skip,
+        );
+
+        let imported = without_duplicates(&storage, import_archive(&archive_path));
+        assert_eq!(imported.len(), 1);
+        assert_eq!(imported[0].tags, vec!["v2"]);
+
+        std::fs::remove_file(archive_path).unwrap();
+    }
+}
@@ -1,10 +1,34 @@
 use crate::config::*;
 use crate::parse::*;
+use crate::provenance::*;
+use crate::resample::*;
+use crate::storage::*;
 
+use colored::*;
 use mongodb::bson;
-use mongodb::bson::{Bson, Regex};
+use mongodb::bson::oid::ObjectId;
+use mongodb::bson::{Bson, Document, Regex};
+use mongodb::error::ErrorKind;
+use mongodb::options::{ClientOptions, FindOptions, Tls, TlsOptions};
 use mongodb::sync::{Client, Collection};
-use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+
+/// Version of the document layout, stored in every document.
+/// 1: one document per executable run (`DataBaseEntry`), no version field
+/// 2: one document per benchmark measurement (`BenchmarkDocument`)
+/// 3: with the date in UTC (`date_utc`) for filtering and ordering
+pub const SCHEMA_VERSION: i32 = 3;
+
+/// Actions on the benchmark collection needed to push, fetch, remove and migrate results.
+const REQUIRED_ACTIONS: &[&str] = &["find", "insert", "remove", "update"];
+
+/// Server error code of operations the user is not authorized for.
+const UNAUTHORIZED_CODE: i32 = 13;
+
+/// Number of documents per round trip when streaming large result sets.
+const STREAM_BATCH_SIZE: u32 = 1000;
 
 #[derive(Clone, Debug)]
 pub struct DataBase {
@@ -13,99 +37,257 @@ pub struct DataBase {
     pub collection: String,
 }
 
-pub enum EntryFilter {
-    ExeName(String),
-    //Tag(String),
-    //All(String, String),
+/// Single benchmark measurement with the denormalized data of the executable run, so
+/// queries for single benchmarks neither fetch nor deserialize the other measurements.
+/// All documents of one `DataBaseEntry` share the same `entry_id`.
+#[derive(Serialize, Deserialize, Debug)]
+struct BenchmarkDocument {
+    schema_version: i32,
+    entry_id: ObjectId,
+    exe_name: String,
+    tags: Vec<String>,
+    meta: BTreeMap<String, String>,
+    provenance: Option<Provenance>,
+    commit: Option<String>,
+    run_id: Option<String>,
+    context: BenchmarkContext,
+    #[serde(default)] // missing in schema version 2
+    date_utc: String,
+    benchmark_index: i32,
+    benchmark: BenchmarkResult,
 }
 
 impl DataBase {
-    pub fn init(config: &AppConfig) -> DataBase {
-        let mongodb_uri = config.mongodb_uri();
+    pub fn init(config: &AppConfig) -> Result<DataBase, Box<dyn Error>> {
+        let database = Self::connect(config)?;
+        // the schema_version index keeps this check from scanning the whole collection
+        database.create_indexes();
+
+        let outdated_document = database
+            .benchmark_collection()
+            .find_one(outdated_documents_filter(), None)?;
+        if outdated_document.is_some() {
+            return Err(format!(
+                "Database collection uses an outdated schema, expected schema version {}, \
+                 run 'beast dbmigrate' first",
+                SCHEMA_VERSION
+            )
+            .into());
+        }
+        Ok(database)
+    }
+
+    fn connect(config: &AppConfig) -> mongodb::error::Result<DataBase> {
         let mongodb_name = config.mongodb_name();
         let mongodb_collection = config.mongodb_collection();
 
-        let client = Client::with_uri_str(mongodb_uri)
-            .expect(&format!("Invalid database uri: {}.", mongodb_uri));
+        let client = Client::with_options(client_options(config)?)?;
 
-        println!("Checking database connection ...");
+        eprintln!("Checking database connection ...");
 
         client
             .database(mongodb_name)
-            .run_command(bson::doc! {"ping": 1}, None)
-            .expect("Could not connect to database!");
+            .run_command(bson::doc! {"ping": 1}, None)?;
 
-        println!("Connected successfully!");
+        eprintln!("Connected successfully!");
 
-        Self {
-            client: client,
+        Ok(Self {
+            client,
             dbname: mongodb_name.to_string(),
             collection: mongodb_collection.to_string(),
-        }
+        })
     }
 
-    pub fn push_last_results(&self, tag: Option<String>) {
-        let tag_value = tag.unwrap_or("".to_string());
-        let benchmark_collection = self.benchmark_collection();
+    /// Checks the connection, the authentication and the permissions step by step and
+    /// prints the outcome of every step. Returns whether all steps succeeded.
+    pub fn test_connection(config: &AppConfig) -> bool {
+        println!("Testing mongodb at {}", mask_uri(config.mongodb_uri()));
 
-        let cumulated_results = parse_cumulated_benchmark_file();
-        let mut docs = vec![];
-        for result in cumulated_results {
-            let result_bson = bson::to_bson(&result).unwrap();
-            let exe_name = exe_name(&result.context.executable);
-            docs.push(bson::doc! {"exe_name": Bson::String(exe_name), "tag": Bson::String(tag_value.clone()), "results": result_bson});
+        let client = match client_options(config).and_then(Client::with_options) {
+            Ok(client) => client,
+            Err(e) => return report_step("Parsing URI and connection options", Err(e)),
+        };
+        let database = client.database(config.mongodb_name());
+        let ping_result = database.run_command(bson::doc! {"ping": 1}, None);
+        if !report_step("Connecting and authenticating", ping_result.map(|_| ())) {
+            return false;
         }
 
-        benchmark_collection
-            .insert_many(docs, None)
-            .expect("Could not insert benchmark results in database collection!");
-    }
-
-    pub fn fetch(&self, entry_filter: EntryFilter) -> Vec<DataBaseEntry> {
-        let benchmark_collection = self.benchmark_collection();
+        let benchmark_collection = database.collection(config.mongodb_collection());
+        let read_result = benchmark_collection.find_one(None, None).map(|_| ());
+        if !report_step("Reading the benchmark collection", read_result) {
+            return false;
+        }
 
-        let filter = match entry_filter {
-            EntryFilter::ExeName(reg_expr) => {
-                bson::doc! { "exe_name": Regex{pattern: reg_expr, options: String::new()} }
-            }
-            //EntryFilter::Tag(tag) => bson::doc! { "tag": tag },
-            //EntryFilter::All(exe, tag) => bson::doc! { "exe_name": exe, "tag": tag },
+        // the privileges are checked instead of writing, so the database stays untouched
+        let step = "Checking the write permissions";
+        let status_result = database.run_command(
+            bson::doc! { "connectionStatus": 1, "showPrivileges": true },
+            None,
+        );
+        let status = match status_result {
+            Ok(status) => status,
+            Err(e) => return report_step(step, Err(e)),
         };
-        println!("Using mongodb query: {}", filter);
+        let missing = missing_actions(&status, config.mongodb_name(), config.mongodb_collection());
+        if missing.is_empty() {
+            println!("{} ... {}", step, "ok".green());
+            true
+        } else {
+            println!("{} ... {}", step, "failed".red());
+            println!(
+                "  The user lacks the {} permissions on {}.{}, it needs the readWrite role \
+                 on the configured database.",
+                missing.join(", "),
+                config.mongodb_name(),
+                config.mongodb_collection()
+            );
+            false
+        }
+    }
 
-        let cursor = benchmark_collection
-            .find(filter, None)
-            .expect("Could not fetch results from database!");
+    /// Converts all documents of previous schema versions to the current one and returns
+    /// the number of converted entries. Every entry is converted on its own, so an
+    /// interrupted migration can simply be restarted.
+    pub fn migrate(config: &AppConfig) -> mongodb::error::Result<u64> {
+        let database = Self::connect(config)?;
+        let benchmark_collection = database.benchmark_collection();
 
-        let mut fetched_results = vec![];
+        // the unique run index of schema version 1 does not allow multiple documents per run,
+        // the date indexes of version 2 are superseded by the ones on date_utc
+        for index in &[
+            "unique_run",
+            "date",
+            "exe_name_date",
+            "benchmark_date",
+            "date_entry_benchmark",
+        ] {
+            let _ = database.client.database(&database.dbname).run_command(
+                bson::doc! { "dropIndexes": &database.collection, "index": *index },
+                None,
+            );
+        }
 
+        let mut migrated_count = 0;
+        let mut migrated_entry_ids = HashSet::new();
+        let cursor = benchmark_collection.find(outdated_documents_filter(), None)?;
         for result in cursor {
-            match result {
-                Ok(document) => {
-                    let entry: DataBaseEntry = bson::from_bson(Bson::Document(document))
+            let document = result?;
+            if document.contains_key("schema_version") {
+                // the documents of schema version 2 only lack the date in UTC
+                let id = document
+                    .get_object_id("_id")
+                    .expect("Database entry has no object id!")
+                    .clone();
+                let benchmark_document: BenchmarkDocument =
+                    bson::from_bson(Bson::Document(document))
                         .expect("Could not deserialize database entry!");
-                    fetched_results.push(entry);
+                benchmark_collection.update_one(
+                    bson::doc! { "_id": id },
+                    bson::doc! { "$set": {
+                        "date_utc": utc_date(&benchmark_document.context.date),
+                        "schema_version": SCHEMA_VERSION,
+                    } },
+                    None,
+                )?;
+                if migrated_entry_ids.insert(benchmark_document.entry_id) {
+                    migrated_count += 1;
+                }
+                continue;
+            }
+
+            let entry_id = document
+                .get_object_id("_id")
+                .expect("Database entry has no object id!")
+                .clone();
+            let entry: DataBaseEntry = bson::from_bson(Bson::Document(document))
+                .expect("Could not deserialize database entry!");
+
+            // reuse the old id to recognize entries converted by an interrupted migration
+            let is_converted = benchmark_collection
+                .find_one(bson::doc! { "entry_id": entry_id.clone() }, None)?
+                .is_some();
+            if !is_converted {
+                let docs = to_documents(&entry, &entry_id);
+                if !docs.is_empty() {
+                    benchmark_collection.insert_many(docs, None)?;
                 }
-                Err(e) => panic!(e),
             }
+            benchmark_collection.delete_one(bson::doc! { "_id": entry_id }, None)?;
+            migrated_count += 1;
         }
 
-        fetched_results
+        database.create_indexes();
+        Ok(migrated_count)
     }
 
-    pub fn list_tags(&self) -> Vec<String> {
-        let benchmark_collection = self.benchmark_collection();
+    /// Creates the indexes of the current schema, existing indexes are left untouched.
+    /// The unique run index lets the database enforce unique run IDs per executable,
+    /// entries without run ID (pushed by previous versions) are excluded from it.
+    fn create_indexes(&self) {
+        let index_result = self.client.database(&self.dbname).run_command(
+            bson::doc! {
+                "createIndexes": &self.collection,
+                "indexes": [
+                    {
+                        "key": { "exe_name": 1, "provenance.run_id": 1, "benchmark_index": 1 },
+                        "name": "unique_run_benchmark",
+                        "unique": true,
+                        "partialFilterExpression": { "provenance.run_id": { "$type": "string" } },
+                    },
+                    { "key": { "schema_version": 1 }, "name": "schema_version" },
+                    { "key": { "entry_id": 1 }, "name": "entry_id" },
+                    // matches the sort of fetch, also used for date ranges
+                    {
+                        "key": { "date_utc": 1, "entry_id": 1, "benchmark_index": 1 },
+                        "name": "date_utc_entry_benchmark",
+                    },
+                    { "key": { "exe_name": 1, "date_utc": 1 }, "name": "exe_name_date_utc" },
+                    { "key": { "benchmark.name": 1, "date_utc": 1 }, "name": "benchmark_date_utc" },
+                    { "key": { "tags": 1 }, "name": "tags" },
+                ],
+            },
+            None,
+        );
+        if let Err(e) = index_result {
+            eprintln!(
+                "Warning: Could not create indexes, the collection might contain duplicate \
+                 runs. Use 'beast dbdedupe' to remove them. ({})",
+                e
+            );
+        }
+    }
+
+    /// Returns the ids of all entries with at least one document matching the filter.
+    fn matching_entry_ids(&self, filter: Document) -> Vec<Bson> {
+        self.benchmark_collection()
+            .distinct("entry_id", filter, None)
+            .expect("Could not fetch results from database!")
+    }
 
-        let tags = benchmark_collection
-            .distinct(
-                "tag",
-                bson::doc! {"tag": {"$exists" : true, "$ne" : ""} },
+    /// Returns the ids of the `last` most recent entries matching the filter.
+    fn last_entry_ids(&self, filter: Document, last: usize) -> Vec<Bson> {
+        let cursor = self
+            .benchmark_collection()
+            .aggregate(
+                vec![
+                    bson::doc! { "$match": filter },
+                    bson::doc! { "$group": {
+                        "_id": "$entry_id",
+                        "date": { "$first": "$date_utc" },
+                    } },
+                    bson::doc! { "$sort": { "date": -1, "_id": -1 } },
+                    bson::doc! { "$limit": last as i64 },
+                ],
                 None,
             )
-            .expect("Could not retrieve list of tags!");
+            .expect("Could not aggregate database entries!");
 
-        tags.iter()
-            .map(|tag| bson::from_bson(tag.clone()).unwrap())
+        cursor
+            .filter_map(|result| {
+                let document = result.expect("Could not read aggregation result!");
+                document.get("_id").cloned()
+            })
             .collect()
     }
 
@@ -116,12 +298,574 @@ impl DataBase {
     }
 }
 
-fn exe_name(pathbuf: &PathBuf) -> String {
-    pathbuf
-        .as_path()
-        .file_name()
-        .unwrap()
-        .to_str()
-        .expect("Could not convert executable path to valid string!")
-        .to_string()
+impl Storage for DataBase {
+    fn push(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) -> u64 {
+        let benchmark_collection = self.benchmark_collection();
+
+        let mut stored_count = 0;
+        for entry in entries {
+            if let Some(run_id) = run_id(entry) {
+                let run_filter =
+                    bson::doc! { "exe_name": &entry.exe_name, "provenance.run_id": run_id };
+                if duplicates == DuplicatePolicy::Replace {
+                    benchmark_collection
+                        .delete_many(run_filter, None)
+                        .expect("Could not replace benchmark results in database collection!");
+                } else {
+                    let is_stored = benchmark_collection
+                        .find_one(run_filter, None)
+                        .expect("Could not fetch results from database!")
+                        .is_some();
+                    if is_stored {
+                        continue;
+                    }
+                }
+            }
+
+            let docs = to_documents(entry, &ObjectId::new());
+            if !docs.is_empty() {
+                benchmark_collection
+                    .insert_many(docs, None)
+                    .expect("Could not insert benchmark results in database collection!");
+            }
+            stored_count += 1;
+        }
+        stored_count
+    }
+
+    fn fetch(&self, entry_filter: &EntryFilter, last: Option<usize>) -> Vec<DataBaseEntry> {
+        let mut filter = filter_document(entry_filter);
+        eprintln!("Using mongodb query: {}", filter);
+
+        if let Some(last) = last {
+            let entry_ids = self.last_entry_ids(filter.clone(), last);
+            filter = bson::doc! { "$and": [filter, { "entry_id": { "$in": entry_ids } }] };
+        }
+
+        // documents of the same entry have to be consecutive to be merged again
+        let find_options = FindOptions::builder()
+            .sort(Some(
+                bson::doc! { "date_utc": 1, "entry_id": 1, "benchmark_index": 1 },
+            ))
+            .build();
+
+        let cursor = self
+            .benchmark_collection()
+            .find(filter, find_options)
+            .expect("Could not fetch results from database!");
+
+        let documents = cursor.map(|result| match result {
+            Ok(document) => bson::from_bson(Bson::Document(document))
+                .expect("Could not deserialize database entry!"),
+            Err(e) => panic!("{}", e),
+        });
+
+        // benchmark filters are already applied to the single measurements
+        let mut entries = vec![];
+        merge_documents(documents, &mut |entry| entries.push(entry));
+        entries
+    }
+
+    fn for_each_entry(&self, entry_filter: &EntryFilter, f: &mut dyn FnMut(DataBaseEntry)) {
+        let filter = filter_document(entry_filter);
+        eprintln!("Using mongodb query: {}", filter);
+
+        // the entry_id index keeps documents of the same entry consecutive without
+        // sorting the whole result set
+        let find_options = FindOptions::builder()
+            .sort(Some(bson::doc! { "entry_id": 1, "benchmark_index": 1 }))
+            .batch_size(Some(STREAM_BATCH_SIZE))
+            .build();
+
+        let cursor = self
+            .benchmark_collection()
+            .find(filter, find_options)
+            .expect("Could not fetch results from database!");
+
+        let documents = cursor.map(|result| match result {
+            Ok(document) => bson::from_bson(Bson::Document(document))
+                .expect("Could not deserialize database entry!"),
+            Err(e) => panic!("{}", e),
+        });
+        merge_documents(documents, f);
+    }
+
+    fn resample(&self, entry_filter: &EntryFilter, resampling: Resampling) -> Vec<ResampledPoint> {
+        let filter = filter_document(entry_filter);
+        eprintln!("Using mongodb query: {}", filter);
+
+        // only the grouping keys and the cpu time leave the server
+        let value = match resampling.statistic {
+            ResampleStatistic::Min => bson::doc! { "$min": "$cpu_time_ns" },
+            ResampleStatistic::Max => bson::doc! { "$max": "$cpu_time_ns" },
+            // $median needs mongoDB 7, so every cpu time of a period is transferred and
+            // the median is computed locally
+            ResampleStatistic::Median => bson::doc! { "$push": "$cpu_time_ns" },
+        };
+        let cursor = self
+            .benchmark_collection()
+            .aggregate(
+                vec![
+                    bson::doc! { "$match": filter },
+                    bson::doc! { "$project": {
+                        "exe_name": 1,
+                        "name": "$benchmark.name",
+                        // like date_utc, the date is null if its format is unknown
+                        "date": { "$dateFromString": {
+                            "dateString": "$date_utc",
+                            "onError": Bson::Null,
+                        } },
+                        "date_utc": 1,
+                        "cpu_time_ns": { "$multiply": ["$benchmark.cpu_time", { "$switch": {
+                            "branches": [
+                                { "case": { "$eq": ["$benchmark.time_unit", "us"] }, "then": 1e3 },
+                                { "case": { "$eq": ["$benchmark.time_unit", "ms"] }, "then": 1e6 },
+                                { "case": { "$eq": ["$benchmark.time_unit", "s"] }, "then": 1e9 },
+                            ],
+                            "default": 1.0,
+                        } }] },
+                    } },
+                    bson::doc! { "$group": {
+                        "_id": {
+                            "exe_name": "$exe_name",
+                            "name": "$name",
+                            "period": period_expression(resampling.period),
+                        },
+                        "value": value,
+                        "count": { "$sum": 1 },
+                    } },
+                    bson::doc! { "$sort": { "_id.period": 1, "_id.exe_name": 1, "_id.name": 1 } },
+                ],
+                None,
+            )
+            .expect("Could not aggregate database entries!");
+
+        cursor
+            .map(|result| {
+                let document = result.expect("Could not read aggregation result!");
+                let id = document.get_document("_id").unwrap();
+                let cpu_time_ns = match document.get("value") {
+                    Some(Bson::Array(values)) => {
+                        let mut samples: Vec<f64> = values.iter().filter_map(to_f64).collect();
+                        resampling.statistic.apply(&mut samples)
+                    }
+                    Some(value) => to_f64(value).unwrap_or_default(),
+                    None => 0.0,
+                };
+                ResampledPoint {
+                    exe_name: id.get_str("exe_name").unwrap_or_default().to_string(),
+                    benchmark_name: id.get_str("name").unwrap_or_default().to_string(),
+                    period_start: id.get_str("period").unwrap_or_default().to_string(),
+                    cpu_time_ns,
+                    sample_count: to_f64(document.get("count").unwrap_or(&Bson::Null))
+                        .unwrap_or_default() as u64,
+                }
+            })
+            .collect()
+    }
+
+    fn delete(&self, entry_filter: &EntryFilter) -> u64 {
+        let filter = filter_document(entry_filter);
+        eprintln!("Using mongodb query: {}", filter);
+
+        // delete whole entries, also if only some of their benchmarks match
+        let entry_ids = self.matching_entry_ids(filter);
+        if entry_ids.is_empty() {
+            return 0;
+        }
+        self.benchmark_collection()
+            .delete_many(bson::doc! { "entry_id": { "$in": &entry_ids } }, None)
+            .expect("Could not delete results from database!");
+        entry_ids.len() as u64
+    }
+
+    fn storage_size(&self) -> Option<u64> {
+        let stats = self
+            .client
+            .database(&self.dbname)
+            .run_command(bson::doc! { "collStats": &self.collection }, None)
+            .ok()?;
+        match stats.get("storageSize") {
+            Some(Bson::Int32(size)) => Some(*size as u64),
+            Some(Bson::Int64(size)) => Some(*size as u64),
+            Some(Bson::Double(size)) => Some(*size as u64),
+            _ => None,
+        }
+    }
+
+    fn remove_duplicates(&self) -> u64 {
+        let benchmark_collection = self.benchmark_collection();
+
+        // object ids increase with creation time, so the first id is the first pushed entry
+        let cursor = benchmark_collection
+            .aggregate(
+                vec![
+                    bson::doc! { "$group": {
+                        "_id": "$entry_id",
+                        "exe_name": { "$first": "$exe_name" },
+                        "run": { "$first": { "$ifNull": [
+                            "$provenance.run_id",
+                            { "$ifNull": ["$run_id", "$context.date"] },
+                        ] } },
+                    } },
+                    bson::doc! { "$sort": { "_id": 1 } },
+                    bson::doc! { "$group": {
+                        "_id": { "exe_name": "$exe_name", "run": "$run" },
+                        "entry_ids": { "$push": "$_id" },
+                        "count": { "$sum": 1 },
+                    } },
+                    bson::doc! { "$match": { "count": { "$gt": 1 } } },
+                ],
+                None,
+            )
+            .expect("Could not aggregate database entries!");
+
+        let mut duplicate_ids = vec![];
+        for result in cursor {
+            let document = result.expect("Could not read aggregation result!");
+            if let Ok(entry_ids) = document.get_array("entry_ids") {
+                duplicate_ids.extend(entry_ids.iter().skip(1).cloned());
+            }
+        }
+        if duplicate_ids.is_empty() {
+            return 0;
+        }
+
+        benchmark_collection
+            .delete_many(bson::doc! { "entry_id": { "$in": &duplicate_ids } }, None)
+            .expect("Could not delete results from database!");
+        self.create_indexes();
+        duplicate_ids.len() as u64
+    }
+}
+
+/// Combines the URI with the credentials and the connection options of the config.
+fn client_options(config: &AppConfig) -> mongodb::error::Result<ClientOptions> {
+    let mut options = ClientOptions::parse(config.mongodb_uri())?;
+
+    if let Some(credentials) = config.db_credentials() {
+        let mut credential = options.credential.take().unwrap_or_default();
+        if credentials.username.is_some() {
+            credential.username = credentials.username;
+        }
+        if credentials.password.is_some() {
+            credential.password = credentials.password;
+        }
+        options.credential = Some(credential);
+    }
+    if let Some(timeout) = config.connect_timeout() {
+        options.connect_timeout = Some(timeout);
+    }
+    if let Some(timeout) = config.server_selection_timeout() {
+        options.server_selection_timeout = Some(timeout);
+    }
+    if let Some(ca_file) = config.tls_ca_file() {
+        let tls_options = TlsOptions::builder()
+            .ca_file_path(Some(ca_file.to_string()))
+            .build();
+        options.tls = Some(Tls::Enabled(tls_options));
+    }
+    Ok(options)
+}
+
+fn report_step(step: &str, result: mongodb::error::Result<()>) -> bool {
+    match result {
+        Ok(()) => {
+            println!("{} ... {}", step, "ok".green());
+            true
+        }
+        Err(e) => {
+            println!("{} ... {}", step, "failed".red());
+            println!("  {}", error_hint(&e));
+            println!("  {}", e);
+            false
+        }
+    }
+}
+
+/// Explains the common connection problems in terms of beast's configuration.
+fn error_hint(e: &mongodb::error::Error) -> String {
+    match e.kind.as_ref() {
+        ErrorKind::AuthenticationError { .. } => format!(
+            "Authentication failed, check the credentials in the URI, the credentials file \
+             or the {} and {} environment variables.",
+            DATABASE_USERNAME_ENV, DATABASE_PASSWORD_ENV
+        ),
+        ErrorKind::ServerSelectionError { .. } => "Server unreachable, check host, port, \
+             TLS settings and network access or increase the server selection timeout."
+            .to_string(),
+        ErrorKind::CommandError(command_error) if command_error.code == UNAUTHORIZED_CODE => {
+            "The user lacks the permission for this operation, it needs the readWrite role \
+             on the configured database."
+                .to_string()
+        }
+        ErrorKind::ArgumentError { .. } | ErrorKind::ParseError { .. } => {
+            "The URI or a connection option is invalid.".to_string()
+        }
+        _ => "Unexpected database error.".to_string(),
+    }
+}
+
+/// Returns the `REQUIRED_ACTIONS` the privileges of a `connectionStatus` reply don't
+/// grant on the benchmark collection.
+fn missing_actions(status: &Document, dbname: &str, collection: &str) -> Vec<&'static str> {
+    let auth_info = match status.get_document("authInfo") {
+        Ok(auth_info) => auth_info,
+        Err(_) => return REQUIRED_ACTIONS.to_vec(),
+    };
+    // without authenticated users the earlier read only succeeded because access
+    // control is disabled, so everything is allowed
+    if auth_info
+        .get_array("authenticatedUsers")
+        .map_or(true, |users| users.is_empty())
+    {
+        return vec![];
+    }
+
+    let mut granted_actions = HashSet::new();
+    let privileges = auth_info
+        .get_array("authenticatedUserPrivileges")
+        .map_or(&[][..], |privileges| privileges.as_slice());
+    for privilege in privileges
+        .iter()
+        .filter_map(|privilege| privilege.as_document())
+    {
+        let resource = match privilege.get_document("resource") {
+            Ok(resource) => resource,
+            Err(_) => continue,
+        };
+        // an empty database or collection name matches all of them, cluster resources
+        // have neither and don't grant access to collections
+        let matches_resource = resource.get_bool("anyResource").unwrap_or(false)
+            || match (resource.get_str("db"), resource.get_str("collection")) {
+                (Ok(db), Ok(coll)) => {
+                    (db.is_empty() || db == dbname) && (coll.is_empty() || coll == collection)
+                }
+                _ => false,
+            };
+        if matches_resource {
+            if let Ok(actions) = privilege.get_array("actions") {
+                granted_actions.extend(actions.iter().filter_map(|action| action.as_str()));
+            }
+        }
+    }
+    REQUIRED_ACTIONS
+        .iter()
+        .filter(|action| !granted_actions.contains(*action))
+        .copied()
+        .collect()
+}
+
+/// Documents of schema version 1 have no version field.
+fn outdated_documents_filter() -> Document {
+    bson::doc! { "$or": [
+        { "schema_version": { "$exists": false } },
+        { "schema_version": { "$lt": SCHEMA_VERSION } },
+    ] }
+}
+
+fn to_documents(entry: &DataBaseEntry, entry_id: &ObjectId) -> Vec<Document> {
+    entry
+        .results
+        .benchmarks
+        .iter()
+        .enumerate()
+        .map(|(benchmark_index, benchmark)| {
+            let document = BenchmarkDocument {
+                schema_version: SCHEMA_VERSION,
+                entry_id: entry_id.clone(),
+                exe_name: entry.exe_name.clone(),
+                tags: entry.tags.clone(),
+                meta: entry.meta.clone(),
+                provenance: entry.provenance.clone(),
+                commit: entry.results.commit.clone(),
+                run_id: entry.results.run_id.clone(),
+                context: entry.results.context.clone(),
+                date_utc: utc_date(&entry.results.context.date),
+                benchmark_index: benchmark_index as i32,
+                benchmark: benchmark.clone(),
+            };
+            bson::to_document(&document).unwrap()
+        })
+        .collect()
+}
+
+/// Merges consecutive documents of the same entry and passes every complete entry to `f`.
+fn merge_documents<I: Iterator<Item = BenchmarkDocument>>(
+    documents: I,
+    f: &mut dyn FnMut(DataBaseEntry),
+) {
+    let mut current: Option<(ObjectId, DataBaseEntry)> = None;
+    for document in documents {
+        if current.as_ref().map(|(entry_id, _)| entry_id) != Some(&document.entry_id) {
+            if let Some((_, entry)) = current.take() {
+                f(entry);
+            }
+            let entry = DataBaseEntry {
+                exe_name: document.exe_name,
+                tags: document.tags,
+                meta: document.meta,
+                provenance: document.provenance,
+                results: BenchmarkResults {
+                    commit: document.commit,
+                    run_id: document.run_id,
+                    provenance: None,
+                    context: document.context,
+                    benchmarks: vec![],
+                },
+            };
+            current = Some((document.entry_id.clone(), entry));
+        }
+        if let Some((_, entry)) = current.as_mut() {
+            entry.results.benchmarks.push(document.benchmark);
+        }
+    }
+    if let Some((_, entry)) = current {
+        f(entry);
+    }
+}
+
+/// Start day of the period of a document as "YYYY-MM-DD" in UTC, like `period_start`
+/// documents with an unknown date format get a period of their own.
+fn period_expression(period: ResamplePeriod) -> Bson {
+    let start = match period {
+        ResamplePeriod::Daily => Bson::String("$date".to_string()),
+        ResamplePeriod::Weekly => Bson::Document(bson::doc! { "$subtract": [
+            "$date",
+            { "$multiply": [{ "$subtract": [{ "$isoDayOfWeek": "$date" }, 1] }, 86400000] },
+        ] }),
+    };
+    // date operators return null for a null date
+    Bson::Document(bson::doc! { "$ifNull": [
+        { "$dateToString": { "format": "%Y-%m-%d", "date": start } },
+        "$date_utc",
+    ] })
+}
+
+fn to_f64(value: &Bson) -> Option<f64> {
+    match value {
+        Bson::Double(value) => Some(*value),
+        Bson::Int32(value) => Some(*value as f64),
+        Bson::Int64(value) => Some(*value as f64),
+        _ => None,
+    }
+}
+
+fn filter_document(entry_filter: &EntryFilter) -> Document {
+    match entry_filter {
+        EntryFilter::ExeName(reg_expr) => bson::doc! { "exe_name": regex(reg_expr) },
+        EntryFilter::Tag(tag) => bson::doc! { "tags": tag },
+        EntryFilter::TagRegex(reg_expr) => bson::doc! { "tags": regex(reg_expr) },
+        EntryFilter::Meta(key, value) => {
+            let key = format!("meta.{}", key);
+            bson::doc! { key: value }
+        }
+        EntryFilter::Benchmark(reg_expr) => bson::doc! { "benchmark.name": regex(reg_expr) },
+        EntryFilter::Since(date) => bson::doc! { "date_utc": { "$gte": utc_date(date) } },
+        EntryFilter::Before(date) => bson::doc! { "date_utc": { "$lt": utc_date(date) } },
+        EntryFilter::Untagged => bson::doc! { "tags": { "$size": 0 } },
+        EntryFilter::Until(date) => {
+            bson::doc! { "date_utc": { "$lt": until_bound(date) } }
+        }
+        EntryFilter::Host(reg_expr) => bson::doc! { "$or": [
+            { "provenance.host_name": regex(reg_expr) },
+            { "context.host_name": regex(reg_expr) },
+        ] },
+        EntryFilter::Provenance(field, reg_expr) => {
+            let key = format!("provenance.{}", field.key());
+            bson::doc! { key: regex(reg_expr) }
+        }
+        EntryFilter::All(filters) => {
+            if filters.is_empty() {
+                return Document::new();
+            }
+            let filter_docs: Vec<Document> = filters.iter().map(filter_document).collect();
+            bson::doc! { "$and": filter_docs }
+        }
+        EntryFilter::Any(filters) => {
+            if filters.is_empty() {
+                return bson::doc! { "_id": { "$in": [] } };
+            }
+            let filter_docs: Vec<Document> = filters.iter().map(filter_document).collect();
+            bson::doc! { "$or": filter_docs }
+        }
+    }
+}
+
+fn regex(reg_expr: &str) -> Regex {
+    Regex {
+        pattern: reg_expr.to_string(),
+        options: String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::test_entry;
+
+    #[test]
+    fn test_missing_actions() {
+        let status = |privileges: Vec<Document>| {
+            bson::doc! { "authInfo": {
+                "authenticatedUsers": [{ "user": "beast", "db": "admin" }],
+                "authenticatedUserPrivileges": privileges,
+            } }
+        };
+        let read_write = status(vec![bson::doc! {
+            "resource": { "db": "beast", "collection": "" },
+            "actions": ["find", "insert", "remove", "update", "createIndex"],
+        }]);
+        assert!(missing_actions(&read_write, "beast", "benchmarks").is_empty());
+        assert_eq!(
+            missing_actions(&read_write, "other", "benchmarks"),
+            REQUIRED_ACTIONS
+        );
+
+        let read = status(vec![
+            bson::doc! {
+                "resource": { "db": "beast", "collection": "benchmarks" },
+                "actions": ["find"],
+            },
+            bson::doc! { "resource": { "cluster": true }, "actions": ["insert"] },
+        ]);
+        assert_eq!(
+            missing_actions(&read, "beast", "benchmarks"),
+            vec!["insert", "remove", "update"]
+        );
+
+        let root = status(vec![bson::doc! {
+            "resource": { "anyResource": true },
+            "actions": ["find", "insert", "remove", "update"],
+        }]);
+        assert!(missing_actions(&root, "beast", "benchmarks").is_empty());
+
+        let no_access_control = bson::doc! { "authInfo": {
+            "authenticatedUsers": [],
+            "authenticatedUserPrivileges": [],
+        } };
+        assert!(missing_actions(&no_access_control, "beast", "benchmarks").is_empty());
+    }
+
+    #[test]
+    fn test_benchmark_documents_roundtrip() {
+        let first_entry = test_entry("simple_benchmark", "v1");
+        let second_entry = test_entry("with_arg_list_benchmark", "");
+
+        let documents = to_documents(&first_entry, &ObjectId::new())
+            .into_iter()
+            .chain(to_documents(&second_entry, &ObjectId::new()))
+            .map(|document| bson::from_bson(Bson::Document(document)).unwrap())
+            .collect::<Vec<BenchmarkDocument>>();
+        assert_eq!(documents.len(), 4);
+        assert_eq!(documents[1].benchmark_index, 1);
+        assert_eq!(documents[1].schema_version, SCHEMA_VERSION);
+
+        let mut entries = vec![];
+        merge_documents(documents.into_iter(), &mut |entry| entries.push(entry));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].tags, vec!["v1"]);
+        assert_eq!(entries[1].exe_name, "with_arg_list_benchmark");
+        assert_eq!(entries[1].results.benchmarks[1].name, "BM_Bar");
+    }
 }
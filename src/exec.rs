@@ -1,13 +1,17 @@
 use crate::parse::*;
+use crate::provenance::*;
 
 use indicatif::{ProgressBar, ProgressStyle};
 use std::env;
 use std::fs::remove_file;
 use std::path::PathBuf;
 use std::process::Command;
+use uuid::Uuid;
 
+/// Runs every executable with the additional `benchmark_args`, e.g. to set repetitions.
 pub fn execute_benchmarks<PathList: AsRef<Vec<PathBuf>>>(
     exe_paths: PathList,
+    benchmark_args: &[String],
 ) -> Vec<BenchmarkResults> {
     let exe_count = exe_paths.as_ref().len() as u64;
     let bar = ProgressBar::new(exe_count);
@@ -23,6 +27,7 @@ pub fn execute_benchmarks<PathList: AsRef<Vec<PathBuf>>>(
         .expect("Could not convert benchmark result file path to str!");
 
     let mut bm_all_results: Vec<BenchmarkResults> = Vec::new();
+    let run_id = Uuid::new_v4().to_string();
 
     for exe_path in exe_paths.as_ref() {
         let exe_name = exe_path.as_path().file_name().unwrap();
@@ -34,6 +39,7 @@ pub fn execute_benchmarks<PathList: AsRef<Vec<PathBuf>>>(
         let benchmark_output = Command::new(exe_path)
             .arg(format!("--benchmark_out={}", result_file_path_str))
             .arg("--benchmark_out_format=json")
+            .args(benchmark_args)
             .output()
             .expect("failed to execute process");
 
@@ -44,12 +50,17 @@ pub fn execute_benchmarks<PathList: AsRef<Vec<PathBuf>>>(
             benchmark_output.status
         );
 
-        let cur_bm_results = parse_single_benchmark_file(&result_file_path);
+        let mut cur_bm_results = parse_single_benchmark_file(&result_file_path);
+        cur_bm_results.run_id = Some(run_id.clone());
+        // collected right away, the checkout may have changed until the results are pushed
+        cur_bm_results.provenance = Some(Provenance::collect(exe_path, Some(run_id.clone())));
 
-        remove_file(result_file_path.as_path()).expect(&format!(
-            "Unable to remove benchmark result file \"{}\"!",
-            result_file_path_str
-        ));
+        remove_file(result_file_path.as_path()).unwrap_or_else(|_| {
+            panic!(
+                "Unable to remove benchmark result file \"{}\"!",
+                result_file_path_str
+            )
+        });
 
         bm_all_results.push(cur_bm_results);
 
@@ -65,5 +76,5 @@ pub fn execute_benchmarks<PathList: AsRef<Vec<PathBuf>>>(
 fn result_file_path() -> PathBuf {
     let mut temp_dir = env::temp_dir();
     temp_dir.push("beast_temp_benchmarkoutput.json");
-    return temp_dir;
+    temp_dir
 }
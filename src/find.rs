@@ -4,7 +4,8 @@ use std::path::{Path, PathBuf};
 use walkdir::{DirEntry, Error, WalkDir};
 
 pub fn find_executables<P: AsRef<Path>>(root: P, exe_pattern: &str) -> Vec<PathBuf> {
-    let re = Regex::new(exe_pattern).expect(&format!("Could not compile regex: {}", exe_pattern));
+    let re = Regex::new(exe_pattern)
+        .unwrap_or_else(|_| panic!("Could not compile regex: {}", exe_pattern));
 
     WalkDir::new(root)
         .into_iter()
@@ -0,0 +1,198 @@
+use crate::parse::*;
+use crate::storage::*;
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Overview of the stored history, e.g. to find out what to plot.
+#[derive(Serialize, Debug)]
+pub struct Inventory {
+    pub entry_count: u64,
+    pub storage_size: Option<u64>,
+    pub executables: Vec<ExecutableSummary>,
+    pub benchmarks: BTreeMap<String, u64>,
+    pub hosts: BTreeMap<String, u64>,
+    pub tags: BTreeMap<String, u64>,
+    pub meta_keys: BTreeMap<String, u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExecutableSummary {
+    pub exe_name: String,
+    pub entry_count: u64,
+    pub first_date: String,
+    pub last_date: String,
+}
+
+/// Summarizes all entries matching the filter.
+pub fn collect_inventory(storage: &dyn Storage, entry_filter: &EntryFilter) -> Inventory {
+    let mut inventory = Inventory {
+        entry_count: 0,
+        storage_size: storage.storage_size(),
+        executables: vec![],
+        benchmarks: BTreeMap::new(),
+        hosts: BTreeMap::new(),
+        tags: BTreeMap::new(),
+        meta_keys: BTreeMap::new(),
+    };
+
+    // entries are streamed, so large histories are never held in memory at once
+    let mut executables: BTreeMap<String, ExecutableSummary> = BTreeMap::new();
+    storage.for_each_entry(entry_filter, &mut |entry| {
+        inventory.entry_count += 1;
+
+        let date = &entry.results.context.date;
+        let summary = executables
+            .entry(entry.exe_name.clone())
+            .or_insert_with(|| ExecutableSummary {
+                exe_name: entry.exe_name.clone(),
+                entry_count: 0,
+                first_date: date.clone(),
+                last_date: date.clone(),
+            });
+        summary.entry_count += 1;
+        if utc_date(date) < utc_date(&summary.first_date) {
+            summary.first_date = date.clone();
+        }
+        if utc_date(date) > utc_date(&summary.last_date) {
+            summary.last_date = date.clone();
+        }
+
+        add_counts(
+            &mut inventory.benchmarks,
+            entry.results.benchmarks.iter().map(|bm| &bm.name),
+        );
+        add_counts(&mut inventory.hosts, host_name(&entry).into_iter());
+        add_counts(&mut inventory.tags, entry.tags.iter());
+        add_counts(&mut inventory.meta_keys, entry.meta.keys());
+    });
+
+    inventory.executables = executables.into_values().collect();
+    inventory
+}
+
+fn add_counts<'a, I: Iterator<Item = &'a String>>(counts: &mut BTreeMap<String, u64>, names: I) {
+    for (name, count) in count_names(names) {
+        *counts.entry(name).or_insert(0) += count;
+    }
+}
+
+pub fn print_inventory(inventory: &Inventory) {
+    let storage_size = match inventory.storage_size {
+        Some(size) => format!(", storage size {}", format_size(size)),
+        None => String::new(),
+    };
+    println!("\n{} entries{}", inventory.entry_count, storage_size);
+
+    let executable_rows = inventory
+        .executables
+        .iter()
+        .map(|summary| {
+            vec![
+                summary.exe_name.clone(),
+                summary.entry_count.to_string(),
+                summary.first_date.clone(),
+                summary.last_date.clone(),
+            ]
+        })
+        .collect();
+    print_table(&["EXECUTABLE", "ENTRIES", "FIRST", "LAST"], executable_rows);
+
+    for (header, counts) in [
+        ("BENCHMARK", &inventory.benchmarks),
+        ("HOST", &inventory.hosts),
+        ("TAG", &inventory.tags),
+        ("METADATA KEY", &inventory.meta_keys),
+    ] {
+        let rows = counts
+            .iter()
+            .map(|(name, count)| vec![name.clone(), count.to_string()])
+            .collect();
+        print_table(&[header, "ENTRIES"], rows);
+    }
+}
+
+/// The provenance knows the host of newer entries, the benchmark context of older ones.
+fn host_name(entry: &DataBaseEntry) -> Option<&String> {
+    entry
+        .provenance
+        .as_ref()
+        .and_then(|provenance| provenance.host_name.as_ref())
+        .or(entry.results.context.host_name.as_ref())
+}
+
+fn print_table(headers: &[&str], rows: Vec<Vec<String>>) {
+    if rows.is_empty() {
+        return;
+    }
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.len()).collect();
+    for row in &rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.len());
+        }
+    }
+
+    let header_row: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    println!();
+    for row in std::iter::once(&header_row).chain(rows.iter()) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    }
+}
+
+fn format_size(size: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KiB", "MiB", "GiB"];
+    let mut value = size as f64;
+    let mut unit_index = 0;
+    while value >= 1024.0 && unit_index < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit_index += 1;
+    }
+    if unit_index == 0 {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit_index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{test_entry, MemoryStorage};
+
+    #[test]
+    fn test_collect_inventory() {
+        let mut old_entry = test_entry("simple_benchmark", "v1");
+        old_entry.results.context.date = "2020-06-15T10:00:00+02:00".to_string();
+        old_entry.results.context.host_name = Some("buildhost".to_string());
+        let storage = MemoryStorage::default();
+        storage.push(
+            &[
+                old_entry,
+                test_entry("simple_benchmark", "v2"),
+                test_entry("with_arg_list_benchmark", ""),
+            ],
+            DuplicatePolicy::Skip,
+        );
+
+        let inventory = collect_inventory(&storage, &EntryFilter::ExeName("simple".to_string()));
+        assert_eq!(inventory.entry_count, 2);
+        assert_eq!(inventory.executables.len(), 1);
+        assert_eq!(
+            inventory.executables[0].first_date,
+            "2020-06-15T10:00:00+02:00"
+        );
+        assert_eq!(
+            inventory.executables[0].last_date,
+            "2021-01-01T00:00:00+00:00"
+        );
+        assert_eq!(inventory.benchmarks.get("BM_Foo"), Some(&2));
+        assert_eq!(inventory.hosts.get("buildhost"), Some(&1));
+        assert_eq!(inventory.tags.len(), 2);
+        assert_eq!(format_size(1536), "1.5 KiB");
+    }
+}
@@ -0,0 +1,372 @@
+use crate::config::*;
+use crate::parse::*;
+use crate::storage::*;
+
+use fs2::FileExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const LOCK_FILENAME: &str = "index.lock";
+const ENTRIES_DIRNAME: &str = "entries";
+const INDEX_DIRNAME: &str = "index";
+
+static ENTRY_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Stores every entry as single json file in a plain directory, e.g. on a network drive
+/// or inside a git repository. A small index file per entry with its filterable fields
+/// avoids parsing every entry on fetch. Pushes and deletions only add and remove files,
+/// so git merges of concurrent pushes never conflict. Entries without an index file,
+/// e.g. copied by hand, are indexed from the entry file itself.
+pub struct JsonDirDataBase {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct IndexEntry {
+    /// The file name of the entry and its index file.
+    #[serde(skip)]
+    id: String,
+    exe_name: String,
+    #[serde(default, alias = "tag", deserialize_with = "deserialize_tags")]
+    tags: Vec<String>,
+    #[serde(default)]
+    meta: BTreeMap<String, String>,
+    #[serde(default)]
+    run_id: Option<String>,
+}
+
+impl IndexEntry {
+    fn from_entry(id: String, entry: &DataBaseEntry) -> IndexEntry {
+        IndexEntry {
+            id,
+            exe_name: entry.exe_name.clone(),
+            tags: entry.tags.clone(),
+            meta: entry.meta.clone(),
+            run_id: run_id(entry).map(String::from),
+        }
+    }
+
+    fn is_same_run(&self, other: &IndexEntry) -> bool {
+        self.run_id.is_some() && self.run_id == other.run_id && self.exe_name == other.exe_name
+    }
+}
+
+impl JsonDirDataBase {
+    pub fn init(config: &AppConfig) -> std::io::Result<JsonDirDataBase> {
+        let dir = Path::new(config.json_dir_path());
+
+        eprintln!("Using json storage directory {} ...", dir.to_string_lossy());
+
+        Self::with_dir(dir)
+    }
+
+    fn with_dir(dir: &Path) -> std::io::Result<JsonDirDataBase> {
+        fs::create_dir_all(dir.join(ENTRIES_DIRNAME))?;
+        fs::create_dir_all(dir.join(INDEX_DIRNAME))?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Locks the index for the lifetime of the returned file handle. Concurrent writers
+    /// on the same directory wait for each other.
+    fn lock_index(&self, exclusive: bool) -> File {
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(self.dir.join(LOCK_FILENAME))
+            .expect("Could not open json storage lock file!");
+        let lock_result = if exclusive {
+            FileExt::lock_exclusive(&lock_file)
+        } else {
+            FileExt::lock_shared(&lock_file)
+        };
+        lock_result.expect("Could not lock json storage index!");
+        lock_file
+    }
+
+    /// Returns the index entries of all stored entries in push order.
+    fn read_index(&self) -> Vec<IndexEntry> {
+        // the entry files decide what is stored, the index files only save parsing them
+        let mut ids: Vec<String> = fs::read_dir(self.dir.join(ENTRIES_DIRNAME))
+            .expect("Could not read json storage directory!")
+            .filter_map(|dir_entry| {
+                let path = dir_entry.ok()?.path();
+                if path.extension()? != "json" {
+                    return None;
+                }
+                Some(path.file_stem()?.to_string_lossy().into_owned())
+            })
+            .collect();
+        ids.sort();
+        ids.into_iter()
+            .filter_map(|id| self.read_index_entry(id))
+            .collect()
+    }
+
+    fn read_index_entry(&self, id: String) -> Option<IndexEntry> {
+        match File::open(self.index_path(&id)) {
+            Ok(f) => {
+                let mut index_entry: IndexEntry = serde_json::from_reader(BufReader::new(f))
+                    .expect("Could not deserialize json storage index!");
+                index_entry.id = id;
+                Some(index_entry)
+            }
+            Err(_) => {
+                let entry = self.read_entry(&id)?;
+                Some(IndexEntry::from_entry(id, &entry))
+            }
+        }
+    }
+
+    fn write_index_entry(&self, index_entry: &IndexEntry) {
+        write_json_atomically(&self.index_path(&index_entry.id), index_entry);
+    }
+
+    /// Removes the entry file first, so the entry is not indexed from its file again.
+    fn remove_entry(&self, id: &str) {
+        // ignore entries which are already gone
+        let _ = fs::remove_file(self.entry_path(id));
+        let _ = fs::remove_file(self.index_path(id));
+    }
+
+    fn index_path(&self, id: &str) -> PathBuf {
+        self.dir.join(INDEX_DIRNAME).join(id.to_string() + ".json")
+    }
+
+    fn entry_path(&self, id: &str) -> PathBuf {
+        self.dir
+            .join(ENTRIES_DIRNAME)
+            .join(id.to_string() + ".json")
+    }
+
+    fn read_entry(&self, id: &str) -> Option<DataBaseEntry> {
+        match File::open(self.entry_path(id)) {
+            Ok(f) => Some(
+                serde_json::from_reader(BufReader::new(f))
+                    .expect("Could not deserialize database entry!"),
+            ),
+            // deleted by a concurrent writer in the meantime
+            Err(_) => None,
+        }
+    }
+
+    /// Uses the index to skip entries which can't match before reading the entry files.
+    fn is_match(&self, index_entry: &IndexEntry, entry_filter: &EntryFilter) -> bool {
+        may_match(index_entry, entry_filter)
+            && self
+                .read_entry(&index_entry.id)
+                .is_some_and(|entry| entry_filter.matches(&entry))
+    }
+}
+
+impl Storage for JsonDirDataBase {
+    fn push(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) -> u64 {
+        let _lock = self.lock_index(true);
+        let mut index = self.read_index();
+        let mut stored_count = 0;
+        for entry in entries {
+            let new_index_entry = IndexEntry::from_entry(unique_entry_id(), entry);
+            let duplicate_position = index
+                .iter()
+                .position(|index_entry| index_entry.is_same_run(&new_index_entry));
+            match (duplicate_position, duplicates) {
+                (Some(_), DuplicatePolicy::Skip) => continue,
+                (Some(position), DuplicatePolicy::Replace) => {
+                    let replaced_index_entry = index.remove(position);
+                    self.remove_entry(&replaced_index_entry.id);
+                }
+                (None, _) => {}
+            }
+            write_json_atomically(&self.entry_path(&new_index_entry.id), entry);
+            self.write_index_entry(&new_index_entry);
+            index.push(new_index_entry);
+            stored_count += 1;
+        }
+        stored_count
+    }
+
+    fn fetch(&self, entry_filter: &EntryFilter, last: Option<usize>) -> Vec<DataBaseEntry> {
+        let index = {
+            let _lock = self.lock_index(false);
+            self.read_index()
+        };
+
+        let mut fetched_results: Vec<DataBaseEntry> = index
+            .iter()
+            .filter(|index_entry| may_match(index_entry, entry_filter))
+            .filter_map(|index_entry| self.read_entry(&index_entry.id))
+            .filter(|entry| entry_filter.matches(entry))
+            .collect();
+
+        retain_matching_benchmarks(&mut fetched_results, entry_filter);
+        sort_and_limit(&mut fetched_results, last);
+        fetched_results
+    }
+
+    fn for_each_entry(&self, entry_filter: &EntryFilter, f: &mut dyn FnMut(DataBaseEntry)) {
+        let index = {
+            let _lock = self.lock_index(false);
+            self.read_index()
+        };
+
+        // entry files are read one at a time instead of loading all matching entries
+        let patterns = benchmark_regexes(entry_filter);
+        for index_entry in index
+            .iter()
+            .filter(|index_entry| may_match(index_entry, entry_filter))
+        {
+            if let Some(mut entry) = self.read_entry(&index_entry.id) {
+                if entry_filter.matches(&entry) && retain_benchmarks_of_entry(&mut entry, &patterns)
+                {
+                    f(entry);
+                }
+            }
+        }
+    }
+
+    fn delete(&self, entry_filter: &EntryFilter) -> u64 {
+        let _lock = self.lock_index(true);
+        let deleted: Vec<IndexEntry> = self
+            .read_index()
+            .into_iter()
+            .filter(|index_entry| self.is_match(index_entry, entry_filter))
+            .collect();
+
+        for index_entry in &deleted {
+            self.remove_entry(&index_entry.id);
+        }
+        deleted.len() as u64
+    }
+
+    fn storage_size(&self) -> Option<u64> {
+        let mut size = 0;
+        for dirname in &[ENTRIES_DIRNAME, INDEX_DIRNAME] {
+            size += fs::read_dir(self.dir.join(dirname))
+                .ok()?
+                .filter_map(|dir_entry| dir_entry.ok()?.metadata().ok())
+                .map(|metadata| metadata.len())
+                .sum::<u64>();
+        }
+        Some(size)
+    }
+
+    fn remove_duplicates(&self) -> u64 {
+        let _lock = self.lock_index(true);
+        let mut keys = HashSet::new();
+        let duplicates: Vec<IndexEntry> = self
+            .read_index()
+            .into_iter()
+            .filter(|index_entry| match self.read_entry(&index_entry.id) {
+                Some(entry) => !keys.insert(entry_key(&entry)),
+                None => false,
+            })
+            .collect();
+
+        for index_entry in &duplicates {
+            self.remove_entry(&index_entry.id);
+        }
+        duplicates.len() as u64
+    }
+}
+
+/// Evaluates all filters on indexed fields, other filters are treated as matching.
+fn may_match(index_entry: &IndexEntry, entry_filter: &EntryFilter) -> bool {
+    match entry_filter {
+        EntryFilter::ExeName(reg_expr) => compile_regex(reg_expr).is_match(&index_entry.exe_name),
+        EntryFilter::Tag(tag) => index_entry.tags.contains(tag),
+        EntryFilter::TagRegex(reg_expr) => {
+            let re = compile_regex(reg_expr);
+            index_entry.tags.iter().any(|tag| re.is_match(tag))
+        }
+        EntryFilter::Meta(key, value) => index_entry.meta.get(key) == Some(value),
+        EntryFilter::Untagged => index_entry.tags.is_empty(),
+        EntryFilter::All(filters) => filters.iter().all(|filter| may_match(index_entry, filter)),
+        EntryFilter::Any(filters) => filters.iter().any(|filter| may_match(index_entry, filter)),
+        _ => true,
+    }
+}
+
+/// Writes to a temporary file first and renames it afterwards, so readers never see
+/// partially written files.
+fn write_json_atomically<T: Serialize>(file_path: &Path, value: &T) {
+    let temp_path = file_path.with_extension(format!("tmp{}", std::process::id()));
+    let f = File::create(&temp_path)
+        .unwrap_or_else(|_| panic!("Could not create file {}!", temp_path.to_string_lossy()));
+    serde_json::to_writer_pretty(&f, value)
+        .unwrap_or_else(|_| panic!("Could not write to file {}!", temp_path.to_string_lossy()));
+    fs::rename(&temp_path, file_path)
+        .unwrap_or_else(|_| panic!("Could not write to file {}!", file_path.to_string_lossy()));
+}
+
+fn unique_entry_id() -> String {
+    let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    format!(
+        "{}_{:09}_{}_{}",
+        since_epoch.as_secs(),
+        since_epoch.subsec_nanos(),
+        std::process::id(),
+        ENTRY_COUNTER.fetch_add(1, Ordering::SeqCst)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::test_entry;
+
+    #[test]
+    fn test_json_dir_storage() {
+        let dir = std::env::temp_dir().join(format!("beast_jsondir_test_{}", std::process::id()));
+        let storage = JsonDirDataBase::with_dir(&dir).unwrap();
+        storage.push(
+            &[
+                test_entry("simple_benchmark", "v1"),
+                test_entry("with_arg_list_benchmark", ""),
+            ],
+            DuplicatePolicy::Skip,
+        );
+
+        let fetched = storage.fetch(&EntryFilter::ExeName("^simple.*".to_string()), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].exe_name, "simple_benchmark");
+        assert_eq!(fetched[0].tags, vec!["v1"]);
+        assert!(storage.storage_size().unwrap() > 0);
+
+        assert_eq!(storage.delete(&EntryFilter::ExeName(".*".to_string())), 2);
+        assert!(storage
+            .fetch(&EntryFilter::ExeName(".*".to_string()), None)
+            .is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_json_dir_entries_without_index_file() {
+        let dir =
+            std::env::temp_dir().join(format!("beast_jsondir_index_test_{}", std::process::id()));
+        let storage = JsonDirDataBase::with_dir(&dir).unwrap();
+        let mut entry = test_entry("simple_benchmark", "v1");
+        entry.results.run_id = Some("run1".to_string());
+        assert_eq!(storage.push(&[entry.clone()], DuplicatePolicy::Skip), 1);
+
+        // e.g. an entry file merged from a git branch without its index file
+        for dir_entry in fs::read_dir(dir.join(INDEX_DIRNAME)).unwrap() {
+            fs::remove_file(dir_entry.unwrap().path()).unwrap();
+        }
+        let fetched = storage.fetch(&EntryFilter::Tag("v1".to_string()), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(storage.push(&[entry], DuplicatePolicy::Skip), 0);
+
+        assert_eq!(storage.delete(&EntryFilter::Tag("v1".to_string())), 1);
+        assert!(storage.fetch(&EntryFilter::All(vec![]), None).is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
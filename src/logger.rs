@@ -5,6 +5,6 @@ pub fn error_and_exit(msg: &str, e: &dyn std::error::Error) -> ! {
     std::process::exit(1);
 }
 
-pub fn _warn(msg: &str, e: &dyn std::error::Error) {
+pub fn warn(msg: &str, e: &dyn std::error::Error) {
     println!("{} {} [{}]!", "WARNING:".yellow(), msg.yellow(), e);
 }
@@ -1,22 +1,43 @@
-use clap::{crate_name, crate_version, App, Arg, ArgMatches, SubCommand};
+use clap::{crate_name, crate_version, App, Arg, ArgGroup, ArgMatches, SubCommand};
 use find::find_executables;
+use std::collections::BTreeMap;
+use std::io::Write;
 use std::path::Path;
 
+mod archive;
 mod config;
 mod database;
 mod exec;
 mod find;
+mod inventory;
+mod jsondir;
 mod logger;
 mod parse;
 mod plot;
+mod provenance;
 mod repocheck;
+mod report;
+mod resample;
+mod retention;
+mod settings;
+mod spool;
+mod sqlite;
+mod storage;
 
+use crate::archive::*;
 use crate::config::*;
-use crate::database::*;
 use crate::exec::*;
+use crate::inventory::*;
 use crate::logger::*;
 use crate::parse::*;
 use crate::plot::*;
+use crate::provenance::*;
+use crate::report::*;
+use crate::resample::*;
+use crate::retention::*;
+use crate::settings::*;
+use crate::spool::*;
+use crate::storage::*;
 
 fn main() -> Result<(), std::io::Error> {
     let matches = App::new(crate_name!())
@@ -29,7 +50,7 @@ fn main() -> Result<(), std::io::Error> {
             Arg::from_usage(
                 "[filter], -f, --filter=[REGEXP] 'Only run benchmark executables matching the regex pattern'",
             )
-            .default_value(".*benchmark[^.]*$"),
+            .default_value(DEFAULT_FILTER),
         )
         .arg(
             Arg::with_name("list")
@@ -42,7 +63,7 @@ fn main() -> Result<(), std::io::Error> {
             Arg::from_usage(
                 "[timeunit], -t, --timeunit=[TIMEUNIT] 'Time unit for plots (possible values are: ms, us, ns)'",
             )
-            .default_value("us"),
+            .default_value(DEFAULT_TIME_UNIT),
         )
         .arg(
             Arg::with_name("lineplot")
@@ -53,15 +74,58 @@ fn main() -> Result<(), std::io::Error> {
             Arg::from_usage(
                 "[xtitle], --xtitle=[STRING] 'Line plot x-axis title to display'",
             )
-            .default_value("N"),
+            .default_value(DEFAULT_XTITLE),
+        )
+        .arg(
+            Arg::from_usage(
+                "[benchmark_args], --benchmark-args=[ARGS] 'Additional arguments for every benchmark executable, e.g. \"--benchmark_repetitions=5\"'",
+            )
+            .allow_hyphen_values(true),
         )
         .arg(
             Arg::with_name("noplot")
                 .help("Do not create plot for benchmark results, e.g. when using beast in scripts")
                 .long("noplot")
         )
+        .arg(
+            Arg::with_name("push")
+                .help("Push the benchmark results to the configured database after the run")
+                .long("push")
+        )
+        .arg(
+            Arg::from_usage(
+                "[tag], --tag=[TAGNAME]... 'Adds a tag to the results pushed with --push, can be repeated'",
+            )
+            .number_of_values(1)
+            .requires("push"),
+        )
+        .arg(
+            Arg::from_usage(
+                "[meta], --meta=[KEY=VALUE]... 'Adds metadata to the results pushed with --push, can be repeated'",
+            )
+            .number_of_values(1)
+            .requires("push"),
+        )
+        .arg(
+            Arg::from_usage(
+                "[profile], --profile=[NAME] 'Selects a named configuration profile, defaults to $BEAST_PROFILE or the default profile'",
+            )
+            .global(true),
+        )
+        .arg(
+            Arg::with_name("read_only")
+                .help("Never write to the preferences directory, e.g. config, spool or repocheck results")
+                .long("read-only")
+                .global(true)
+        )
         .subcommand(SubCommand::with_name("config")
             .about("Handle beast's configuration, e.g. the mongodb access or the git settings")
+            .arg(
+                Arg::from_usage(
+                    "[db_backend], --set-db-backend=[BACKEND] 'Sets the storage backend for push/fetch of benchmark results'",
+                )
+                .possible_values(STORAGE_BACKENDS),
+            )
             .arg(
                 Arg::from_usage(
                     "[mongodb_uri], --set-db-uri=[URI] 'Sets a mongodb URI for push/fetch of benchmark results'",
@@ -77,11 +141,69 @@ fn main() -> Result<(), std::io::Error> {
                     "[mongodb_collection], --set-db-collection=[COLLECTION] 'Sets a mongodb collection to work with'",
                 ),
             )
+            .arg(
+                Arg::from_usage(
+                    "[sqlite_path], --set-sqlite-path=[PATH] 'Sets the sqlite database file used by the sqlite backend'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[json_dir_path], --set-json-dir=[DIR] 'Sets the results directory used by the jsondir backend'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[credentials_path], --set-db-credentials-file=[PATH] 'Sets a yaml file with the database username and password, only readable by its owner'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[connect_timeout], --set-db-connect-timeout=[MS] 'Sets the timeout for connecting to the mongodb server'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[server_selection_timeout], --set-db-server-selection-timeout=[MS] 'Sets the timeout for finding a suitable mongodb server'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[tls_ca_file], --set-db-tls-ca-file=[PATH] 'Sets the CA file to verify the mongodb server with, enables TLS'",
+                ),
+            )
             .arg(
                 Arg::from_usage(
                     "[repocheck_yaml_path], --set-repocheck-yaml=[PATH] 'Sets path to the repocheck settings yaml file'",
                 ),
             )
+            .arg(
+                Arg::from_usage(
+                    "[unset], --unset=[KEY] 'Resets a stored config value to its default'",
+                )
+                .possible_values(CONFIG_KEYS)
+                .multiple(true)
+                .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("reset")
+                .help("Resets all stored config values of the profile to their defaults")
+                .long("reset")
+            )
+            .arg(
+                Arg::with_name("explain")
+                .help("Shows the effective settings and where each value came from")
+                .long("explain")
+            )
+            .arg(
+                Arg::with_name("list_profiles")
+                .help("Lists all configuration profiles, the selected one is marked")
+                .long("list-profiles")
+            )
+            .arg(
+                Arg::with_name("test_db")
+                .help("Tests the connection and permissions of the configured database")
+                .long("test-db")
+            )
         )
         .subcommand(SubCommand::with_name("plotlast")
             .about("Plots benchmark results from last run \n\
@@ -91,22 +213,114 @@ fn main() -> Result<(), std::io::Error> {
             .about("Pushes previously exported benchmark results to the configured database")
             .arg(
                 Arg::from_usage(
-                    "[tag], --tag=[TAGNAME] 'Adds a tag to the pushed results.'",
-                ),
+                    "[tag], --tag=[TAGNAME]... 'Adds a tag to the pushed results, can be repeated'",
+                )
+                .number_of_values(1),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[meta], --meta=[KEY=VALUE]... 'Adds metadata to the pushed results, can be repeated'",
+                )
+                .number_of_values(1),
+            )
+            .arg(
+                Arg::with_name("replace")
+                .help("Replaces results of the same run already stored instead of skipping them")
+                .long("replace")
             )
         )
         .subcommand(SubCommand::with_name("dbplot")
             .about("Fetches all benchmark results from the configured database collection and plot them as time series \n\
                     Note: Supports the '-t' option after main command to plot with desired time unit.")
+            .args(&entry_filter_args())
+            .arg(
+                Arg::from_usage(
+                    "[last], --last=[N] 'Only plot the N most recent matching results'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[resample], --resample=[PERIOD] 'Aggregates the cpu times of each benchmark per period, computed by the database where supported'",
+                )
+                .possible_values(RESAMPLE_PERIODS)
+                .conflicts_with("last"),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[statistic], --statistic=[STATISTIC] 'Statistic of the cpu times within a resampled period'",
+                )
+                .possible_values(RESAMPLE_STATISTICS)
+                .default_value("median")
+                .requires("resample"),
+            )
             .arg(
                 Arg::from_usage(
-                    "[fetchfilter], --fetchfilter=[REGEXP] 'Filters executables to plot with a mongodb compatible regexp'",
+                    "[x_axis], --x-axis=[AXIS] 'Plots the results over their date, commit or tag'",
                 )
-                .default_value(".*"),
+                .possible_values(PLOT_X_AXES)
+                .default_value("date"),
+            )
+        )
+        .subcommand(SubCommand::with_name("dbrm")
+            .about("Removes benchmark results matching the given filters from the configured database \n\
+                    Note: '--benchmark' selects whole results containing matching benchmarks.")
+            .args(&entry_filter_args())
+            .args(&confirmation_args())
+        )
+        .subcommand(SubCommand::with_name("dbprune")
+            .about("Removes benchmark results from the configured database according to retention policies")
+            .arg(
+                Arg::from_usage(
+                    "[keep_last], --keep-last=[N] 'Keeps only the N most recent results per executable'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[drop_untagged_older_than], --drop-untagged-older-than=[DAYS] 'Removes untagged results older than the given number of days'",
+                ),
+            )
+            .group(
+                ArgGroup::with_name("policies")
+                .args(&["keep_last", "drop_untagged_older_than"])
+                .multiple(true)
+                .required(true),
+            )
+            .args(&confirmation_args())
+        )
+        .subcommand(SubCommand::with_name("dbexport")
+            .about("Exports benchmark results matching the given filters to a portable archive file")
+            .arg(
+                Arg::from_usage(
+                    "<output> 'Archive file to write (JSON Lines)'",
+                ),
             )
+            .args(&entry_filter_args())
+        )
+        .subcommand(SubCommand::with_name("dbimport")
+            .about("Imports benchmark results from an archive file into the configured database, skipping duplicates")
+            .arg(
+                Arg::from_usage(
+                    "<input> 'Archive file created by dbexport'",
+                ),
+            )
+        )
+        .subcommand(SubCommand::with_name("dbmigrate")
+            .about("Converts a mongodb collection created by previous versions to the current schema")
+        )
+        .subcommand(SubCommand::with_name("dbsync")
+            .about("Pushes results which were spooled locally while the configured database was unreachable")
+        )
+        .subcommand(SubCommand::with_name("dbdedupe")
+            .about("Removes duplicate benchmark results of the same run from the configured database, keeping the first pushed one")
         )
         .subcommand(SubCommand::with_name("dblist")
-            .about("Lists distinct tags in current benchmark collection")
+            .about("Lists executables, benchmarks, hosts, tags and metadata keys of the results matching the given filters")
+            .args(&entry_filter_args())
+            .arg(
+                Arg::with_name("json")
+                .help("Print the inventory as json, e.g. for scripts")
+                .long("json")
+            )
         )
         .subcommand(SubCommand::with_name("repocheck")
             .about("Runs beast for the commit range previously specified in the yaml set via 'beast config'")
@@ -120,33 +334,97 @@ fn main() -> Result<(), std::io::Error> {
                 .help("Plot repocheck results from previous run, configured in the according yaml")
                 .long("plot")
             )
+            .arg(
+                Arg::from_usage(
+                    "[on_build_failure], --on-build-failure=[ACTION] 'Skip commits which fail to build or abort the run, defaults to skip'",
+                )
+                .possible_values(repocheck::BUILD_FAILURE_ACTIONS),
+            )
+            .arg(
+                Arg::with_name("keep_worktree")
+                .help("Keep the temporary worktree the commits are built in, e.g. to debug a build")
+                .long("keep-worktree")
+            )
+            .arg(
+                Arg::with_name("bisect")
+                .help("Binary searches the commit range for the first commit making a benchmark slower")
+                .long("bisect")
+                .requires("bisect_benchmark")
+                .conflicts_with("plot")
+            )
+            .arg(
+                Arg::from_usage(
+                    "[bisect_benchmark], --benchmark=[REGEX] 'Benchmarks to check for a regression when bisecting'",
+                )
+                .requires("bisect"),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[bisect_threshold], --threshold=[PERCENT] 'Cpu time increase considered a regression when bisecting, defaults to 10%'",
+                )
+                .requires("bisect"),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[confirm_repetitions], --confirm=[REPETITIONS] 'Confirms the found regression by benchmarking with repetitions'",
+                )
+                .requires("bisect"),
+            )
+        )
+        .subcommand(SubCommand::with_name("report")
+            .about("Compares the last benchmark results against a baseline results file \n\
+                    Note: Supports the '-t' option after main command to report with desired time unit.")
+            .arg(
+                Arg::with_name("markdown")
+                .help("Print the comparison as markdown, e.g. to post it as pull request comment")
+                .long("markdown")
+            )
+            .arg(
+                Arg::from_usage(
+                    "[baseline], --baseline=[FILE] 'Results file to compare against (see --save-baseline)'",
+                )
+                .required_unless("save_baseline"),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[current], --current=[FILE] 'Results file to compare, defaults to the results of the last run'",
+                ),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[threshold], --threshold=[PERCENT] 'Relative cpu time change to consider significant'",
+                )
+                .default_value(DEFAULT_THRESHOLD),
+            )
+            .arg(
+                Arg::from_usage(
+                    "[save_baseline], --save-baseline=[FILE] 'Saves the results of the last run as baseline file'",
+                )
+                .conflicts_with("baseline"),
+            )
         )
         .get_matches();
 
-    let mut config = AppConfig::init();
+    let settings = Settings::resolve(&matches);
+    let mut config = AppConfig::init(&settings.profile.value, settings.read_only.value);
+    settings.apply_project_db_config(&mut config);
+    config.apply_env_overrides();
 
     // Handle subcommands
-    handle_config_commands(&matches, &mut config);
-    handle_database_commands(&matches, &config);
-    handle_repocheck_commands(&matches, &config);
+    handle_config_commands(&matches, &mut config, &settings);
+    handle_database_commands(&matches, &config, &settings);
+    handle_repocheck_commands(&matches, &config, &settings);
+    handle_report_commands(&matches, &settings);
 
     // Parse main options
-    let root_dir = match matches.value_of("rootdir") {
-        Some(valid_val) => Path::new(valid_val).to_path_buf(),
-        _ => match std::env::current_dir() {
-            Ok(path_buf) => path_buf,
-            Err(err) => panic!("Can't retrieve current directory: {:?}", err),
-        },
-    };
-
-    let filter_pattern = matches.value_of("filter").unwrap();
-    let plot_time_unit = matches.value_of("timeunit").unwrap();
+    let filter_pattern = settings.filter.value.as_str();
+    let plot_time_unit = settings.time_unit.value.as_str();
 
     // Plot last results
-    if let Some(ref _matches) = matches.subcommand_matches("plotlast") {
+    if let Some(_matches) = matches.subcommand_matches("plotlast") {
         let last_results = parse::parse_cumulated_benchmark_file();
-        if matches.is_present("lineplot") {
-            let x_title = matches.value_of("xtitle").unwrap();
+        if settings.lineplot.value {
+            let x_title = settings.xtitle.value.as_str();
             plot_all_as_lines(&last_results, plot_time_unit, x_title);
         } else {
             plot_all_as_bars(&last_results, plot_time_unit);
@@ -155,9 +433,11 @@ fn main() -> Result<(), std::io::Error> {
     }
 
     // Benchmark execution handling
-    println!("Root scan directory: {:?}", root_dir.as_os_str());
-
-    let mut benchmark_paths = find_executables(root_dir, filter_pattern);
+    let mut benchmark_paths = vec![];
+    for root_dir in &settings.root_dirs.value {
+        println!("Root scan directory: {:?}", root_dir.as_os_str());
+        benchmark_paths.append(&mut find_executables(root_dir, filter_pattern));
+    }
 
     if benchmark_paths.is_empty() {
         println!("No benchmarks found to run!");
@@ -179,103 +459,366 @@ fn main() -> Result<(), std::io::Error> {
         return Ok(());
     }
 
-    let benchmark_results = execute_benchmarks(benchmark_paths);
+    let benchmark_results = execute_benchmarks(benchmark_paths, &settings.benchmark_args.value);
     export_cumulated_results(&benchmark_results);
 
-    if !matches.is_present("noplot") {
-        if matches.is_present("lineplot") {
-            let x_title = matches.value_of("xtitle").unwrap();
+    if matches.is_present("push") {
+        if config.is_db_config_set() {
+            let entries = entries_from_results(
+                benchmark_results.clone(),
+                &values_of(&matches, "tag"),
+                &meta_from_matches(&matches),
+            );
+            if let Some(stored_count) = push_or_spool(&config, &entries, DuplicatePolicy::Skip) {
+                println!("Pushed {} results.", stored_count);
+            }
+        } else {
+            print_config_not_set();
+        }
+    }
+
+    if !settings.noplot.value {
+        if settings.lineplot.value {
+            let x_title = settings.xtitle.value.as_str();
             plot_all_as_lines(&benchmark_results, plot_time_unit, x_title);
         } else {
             plot_all_as_bars(&benchmark_results, plot_time_unit);
         }
     }
 
-    return Ok(());
+    Ok(())
 }
 
-fn handle_config_commands(matches: &ArgMatches, config: &mut AppConfig) {
-    if let Some(ref matches) = matches.subcommand_matches("config") {
-        config.print();
-        match matches.value_of("mongodb_uri") {
-            Some(provided_url) => config.set_mongodb_uri(&provided_url.to_string()),
-            None => {}
+fn handle_config_commands(matches: &ArgMatches, config: &mut AppConfig, settings: &Settings) {
+    if let Some(matches) = matches.subcommand_matches("config") {
+        if matches.is_present("explain") {
+            settings.print_explanation(config);
+            std::process::exit(0);
         }
-        match matches.value_of("mongodb_dbname") {
-            Some(provided_mongodb_name) => {
-                config.set_mongodb_name(&provided_mongodb_name.to_string())
+        if matches.is_present("list_profiles") {
+            for profile in list_profiles() {
+                let marker = if profile == config.profile() {
+                    "*"
+                } else {
+                    " "
+                };
+                println!("{} {}", marker, profile);
             }
-            None => {}
+            std::process::exit(0);
+        }
+        if !config.profile_exists() {
+            println!(
+                "Profile '{}' does not exist yet, it is created with the first '--set-...' option.\n",
+                config.profile()
+            );
         }
-        match matches.value_of("mongodb_collection") {
-            Some(provided_mongodb_collection) => {
-                config.set_mongodb_collection(&provided_mongodb_collection.to_string())
+        config.print();
+        if matches.is_present("reset") {
+            config.reset();
+        }
+        if let Some(keys) = matches.values_of("unset") {
+            for key in keys {
+                config.unset_value(key);
             }
-            None => {}
-        }
-        match matches.value_of("repocheck_yaml_path") {
-            Some(yaml_path) => {
-                match std::fs::canonicalize(yaml_path) {
-                    Ok(path) => config
-                        .set_repocheck_config_yaml(&path.as_path().to_string_lossy().to_string()),
-                    Err(e) => error_and_exit(
-                        &format!("Path '{}' does not exist or can't be read", yaml_path),
-                        &e,
+        }
+        if let Some(provided_backend) = matches.value_of("db_backend") {
+            config.set_storage_backend(provided_backend)
+        }
+        if let Some(provided_url) = matches.value_of("mongodb_uri") {
+            config.set_mongodb_uri(provided_url)
+        }
+        if let Some(provided_mongodb_name) = matches.value_of("mongodb_dbname") {
+            config.set_mongodb_name(provided_mongodb_name)
+        }
+        if let Some(provided_mongodb_collection) = matches.value_of("mongodb_collection") {
+            config.set_mongodb_collection(provided_mongodb_collection)
+        }
+        if let Some(sqlite_path) = matches.value_of("sqlite_path") {
+            let sqlite_path = std::env::current_dir().unwrap().join(sqlite_path);
+            config.set_sqlite_path(&sqlite_path.to_string_lossy())
+        }
+        if let Some(json_dir_path) = matches.value_of("json_dir_path") {
+            let json_dir_path = std::env::current_dir().unwrap().join(json_dir_path);
+            config.set_json_dir_path(&json_dir_path.to_string_lossy())
+        }
+        if let Some(credentials_path) = matches.value_of("credentials_path") {
+            match std::fs::canonicalize(credentials_path) {
+                Ok(path) => config.set_credentials_path(&path.as_path().to_string_lossy()),
+                Err(e) => error_and_exit(
+                    &format!(
+                        "Path '{}' does not exist or can't be read",
+                        credentials_path
                     ),
-                };
+                    &e,
+                ),
+            };
+        }
+        if let Some(timeout) = matches.value_of("connect_timeout") {
+            match timeout.parse::<u64>() {
+                Ok(timeout) => config.set_connect_timeout(timeout),
+                Err(e) => error_and_exit("Invalid value for --set-db-connect-timeout", &e),
             }
-            None => {}
+        }
+        if let Some(timeout) = matches.value_of("server_selection_timeout") {
+            match timeout.parse::<u64>() {
+                Ok(timeout) => config.set_server_selection_timeout(timeout),
+                Err(e) => error_and_exit("Invalid value for --set-db-server-selection-timeout", &e),
+            }
+        }
+        if let Some(ca_file) = matches.value_of("tls_ca_file") {
+            match std::fs::canonicalize(ca_file) {
+                Ok(path) => config.set_tls_ca_file(&path.as_path().to_string_lossy()),
+                Err(e) => error_and_exit(
+                    &format!("Path '{}' does not exist or can't be read", ca_file),
+                    &e,
+                ),
+            };
+        }
+        if let Some(yaml_path) = matches.value_of("repocheck_yaml_path") {
+            match std::fs::canonicalize(yaml_path) {
+                Ok(path) => config.set_repocheck_config_yaml(&path.as_path().to_string_lossy()),
+                Err(e) => error_and_exit(
+                    &format!("Path '{}' does not exist or can't be read", yaml_path),
+                    &e,
+                ),
+            };
+        }
+        if matches.is_present("test_db") {
+            println!();
+            if !config.is_db_config_set() {
+                print_config_not_set();
+                std::process::exit(1);
+            }
+            let success = test_db_connection(config);
+            std::process::exit(if success { 0 } else { 1 });
         }
         std::process::exit(0);
     }
 }
 
-fn handle_database_commands(matches: &ArgMatches, config: &AppConfig) {
-    let plot_time_unit = matches.value_of("timeunit").unwrap();
+fn handle_database_commands(matches: &ArgMatches, config: &AppConfig, settings: &Settings) {
+    let plot_time_unit = settings.time_unit.value.as_str();
 
-    if let Some(ref submatches) = matches.subcommand_matches("dbpush") {
+    if let Some(submatches) = matches.subcommand_matches("dbpush") {
         if config.is_db_config_set() {
-            let db = DataBase::init(&config);
-            let tag_option = submatches.value_of("tag").map(String::from);
-            db.push_last_results(tag_option);
+            let tags = values_of(submatches, "tag");
+            let meta = meta_from_matches(submatches);
+            let duplicates = if submatches.is_present("replace") {
+                DuplicatePolicy::Replace
+            } else {
+                DuplicatePolicy::Skip
+            };
+            let entries = entries_from_last_results(&tags, &meta);
+            if let Some(stored_count) = push_or_spool(config, &entries, duplicates) {
+                println!("Pushed {} results.", stored_count);
+                if stored_count < entries.len() as u64 {
+                    println!(
+                        "Skipped {} results of runs already stored, use '--replace' to overwrite them.",
+                        entries.len() as u64 - stored_count
+                    );
+                }
+            }
         } else {
             print_config_not_set();
         }
         std::process::exit(0);
     }
-    if let Some(ref submatches) = matches.subcommand_matches("dbplot") {
+    if let Some(submatches) = matches.subcommand_matches("dbplot") {
         if config.is_db_config_set() {
-            let filter_pattern = submatches.value_of("fetchfilter").unwrap_or(".*");
-            let db = DataBase::init(&config);
+            let entry_filter = entry_filter_from_matches(submatches);
+            let last = submatches
+                .value_of("last")
+                .map(|last| match last.parse::<usize>() {
+                    Ok(last) => last,
+                    Err(e) => error_and_exit("Invalid value for --last", &e),
+                });
+            let storage = open_storage(config);
+
+            if let Some(period) = submatches.value_of("resample") {
+                let resampling = Resampling {
+                    period: ResamplePeriod::from_name(period).unwrap(),
+                    statistic: ResampleStatistic::from_name(
+                        submatches.value_of("statistic").unwrap(),
+                    )
+                    .unwrap(),
+                };
+                let points = storage.resample(&entry_filter, resampling);
 
-            let results = db.fetch(EntryFilter::ExeName(filter_pattern.to_string()));
-            //TODO: add support for "tag" + "both"
+                if points.is_empty() {
+                    println!("Did not find any matching results. Nothing to plot!");
+                    std::process::exit(0);
+                }
+
+                plot_resampled_points(&points, resampling.statistic, plot_time_unit);
+                std::process::exit(0);
+            }
+
+            let results = storage.fetch(&entry_filter, last);
 
             if results.is_empty() {
                 println!("Did not find any matching results. Nothing to plot!");
                 std::process::exit(0);
             }
 
-            plot_db_entries(&results, plot_time_unit);
+            let x_axis = PlotXAxis::from_name(submatches.value_of("x_axis").unwrap()).unwrap();
+            plot_db_entries(&results, plot_time_unit, x_axis);
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(submatches) = matches.subcommand_matches("dbrm") {
+        if config.is_db_config_set() {
+            if !ENTRY_FILTER_ARGS
+                .iter()
+                .any(|name| submatches.occurrences_of(name) > 0)
+            {
+                error_and_exit(
+                    "Refusing to remove all results",
+                    &std::io::Error::new(
+                        std::io::ErrorKind::InvalidInput,
+                        "select the results to remove with at least one filter, e.g. --fetchfilter",
+                    ),
+                );
+            }
+            let storage = open_storage(config);
+            delete_with_confirmation(
+                storage.as_ref(),
+                &entry_filter_from_matches(submatches),
+                submatches,
+            );
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(submatches) = matches.subcommand_matches("dbprune") {
+        if config.is_db_config_set() {
+            let storage = open_storage(config);
+            let mut filters = vec![];
+            if let Some(keep_last) = submatches.value_of("keep_last") {
+                let keep_last = match keep_last.parse::<usize>() {
+                    Ok(keep_last) => keep_last,
+                    Err(e) => error_and_exit("Invalid value for --keep-last", &e),
+                };
+                filters.append(&mut prune_filters(
+                    storage.as_ref(),
+                    &PrunePolicy::KeepLast(keep_last),
+                ));
+            }
+            if let Some(days) = submatches.value_of("drop_untagged_older_than") {
+                let days = match days.parse::<i64>() {
+                    Ok(days) => days,
+                    Err(e) => error_and_exit("Invalid value for --drop-untagged-older-than", &e),
+                };
+                filters.append(&mut prune_filters(
+                    storage.as_ref(),
+                    &PrunePolicy::DropUntaggedOlderThan(days),
+                ));
+            }
+            // the policies may select the same results, so they are removed as a union
+            delete_with_confirmation(storage.as_ref(), &EntryFilter::Any(filters), submatches);
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(submatches) = matches.subcommand_matches("dbexport") {
+        if config.is_db_config_set() {
+            let storage = open_storage(config);
+            let entries = storage.fetch(&entry_filter_from_matches(submatches), None);
+            let output_path = Path::new(submatches.value_of("output").unwrap());
+            export_archive(&entries, output_path);
+            println!(
+                "Exported {} results to {}.",
+                entries.len(),
+                output_path.to_string_lossy()
+            );
         } else {
             print_config_not_set();
         }
         std::process::exit(0);
     }
-    if let Some(ref _submatches) = matches.subcommand_matches("dblist") {
+    if let Some(submatches) = matches.subcommand_matches("dbimport") {
         if config.is_db_config_set() {
-            let db = DataBase::init(&config);
-            let tags = db.list_tags();
-            print!("\nFound tags:\n{:?}\n", tags);
+            let entries = import_archive(Path::new(submatches.value_of("input").unwrap()));
+            let archive_count = entries.len();
+            let storage = open_storage(config);
+            let new_entries = without_duplicates(storage.as_ref(), entries);
+            let stored_count = storage.push(&new_entries, DuplicatePolicy::Skip);
+            println!(
+                "Imported {} results, skipped {} duplicates.",
+                stored_count,
+                archive_count as u64 - stored_count
+            );
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(_submatches) = matches.subcommand_matches("dbmigrate") {
+        if config.is_db_config_set() {
+            if config.storage_backend() == "mongodb" {
+                match database::DataBase::migrate(config) {
+                    Ok(migrated_count) => println!(
+                        "Migrated {} results to schema version {}.",
+                        migrated_count,
+                        database::SCHEMA_VERSION
+                    ),
+                    Err(e) => error_and_exit("Could not migrate database collection", &e),
+                }
+            } else {
+                println!("Only the mongodb backend has a schema to migrate.");
+            }
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(_submatches) = matches.subcommand_matches("dbsync") {
+        if config.is_db_config_set() {
+            if Spool::open(config).pending_count() == 0 {
+                println!("No spooled results to push.");
+            } else {
+                config.ensure_writable("remove pushed results from the spool");
+                let storage = open_storage(config);
+                flush_spool(config, storage.as_ref());
+            }
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(_submatches) = matches.subcommand_matches("dbdedupe") {
+        if config.is_db_config_set() {
+            let storage = open_storage(config);
+            println!("Removed {} duplicate results.", storage.remove_duplicates());
+        } else {
+            print_config_not_set();
+        }
+        std::process::exit(0);
+    }
+    if let Some(submatches) = matches.subcommand_matches("dblist") {
+        if config.is_db_config_set() {
+            let storage = open_storage(config);
+            let inventory =
+                collect_inventory(storage.as_ref(), &entry_filter_from_matches(submatches));
+            if submatches.is_present("json") {
+                println!("{}", serde_json::to_string_pretty(&inventory).unwrap());
+            } else {
+                print_inventory(&inventory);
+            }
+        } else {
+            print_config_not_set();
         }
         std::process::exit(0);
     }
 }
 
-fn handle_repocheck_commands(matches: &ArgMatches, config: &AppConfig) {
-    let plot_time_unit = matches.value_of("timeunit").unwrap();
+fn handle_repocheck_commands(matches: &ArgMatches, config: &AppConfig, settings: &Settings) {
+    let plot_time_unit = settings.time_unit.value.as_str();
 
-    if let Some(ref submatches) = matches.subcommand_matches("repocheck") {
+    if let Some(submatches) = matches.subcommand_matches("repocheck") {
         let yaml_path = Path::new(config.repocheck_config_yaml());
         let mut settings = repocheck::parse(yaml_path);
 
@@ -283,17 +826,278 @@ fn handle_repocheck_commands(matches: &ArgMatches, config: &AppConfig) {
             settings.no_clean = Some(true);
         }
 
+        if submatches.is_present("keep_worktree") {
+            settings.keep_worktree = Some(true);
+        }
+
+        if let Some(action) = submatches.value_of("on_build_failure") {
+            settings.on_build_failure = repocheck::BuildFailureAction::from_name(action);
+        }
+
         if submatches.is_present("plot") {
             let results = repocheck::collect_repocheck_results(&settings);
-            plot_all_as_commit_series(&results, plot_time_unit);
+            if !results.failed_commits.is_empty() {
+                println!(
+                    "Build failed for commits: {}",
+                    results.failed_commits.join(", ")
+                );
+            }
+            plot_all_as_commit_series(&results.results, &results.commits, plot_time_unit);
+            std::process::exit(0);
+        }
+
+        // results of every commit are kept in the preferences directory
+        config.ensure_writable("run repocheck");
+
+        if submatches.is_present("bisect") {
+            let bisect_settings = bisect_settings_from_matches(submatches);
+            repocheck::bisect(&settings, &bisect_settings);
             std::process::exit(0);
         }
 
-        repocheck::run(&settings);
+        if let Some(true) = settings.push {
+            if !config.is_db_config_set() {
+                print_config_not_set();
+                std::process::exit(0);
+            }
+        }
+
+        repocheck::run(&settings, config);
         std::process::exit(0);
     }
 }
 
+fn handle_report_commands(matches: &ArgMatches, settings: &Settings) {
+    let report_time_unit = settings.time_unit.value.as_str();
+
+    if let Some(submatches) = matches.subcommand_matches("report") {
+        if let Some(baseline_path) = submatches.value_of("save_baseline") {
+            let last_results = parse_cumulated_benchmark_file();
+            export_results_to_file(&last_results, Path::new(baseline_path)).unwrap_or_else(|e| {
+                error_and_exit(&format!("Could not write to file {}", baseline_path), &e)
+            });
+            println!("Saved last results as baseline '{}'.", baseline_path);
+            std::process::exit(0);
+        }
+
+        let threshold = settings.threshold.value;
+
+        let baseline = parse_results_file(submatches.value_of("baseline").unwrap());
+        let current = match submatches.value_of("current") {
+            Some(current_path) => parse_results_file(current_path),
+            None => parse_cumulated_benchmark_file(),
+        };
+
+        let comparisons = compare_results(&baseline, &current, threshold);
+        if submatches.is_present("markdown") {
+            print!(
+                "{}",
+                markdown_report(&comparisons, &current, report_time_unit, threshold)
+            );
+        } else {
+            print!("{}", text_report(&comparisons, report_time_unit));
+        }
+        std::process::exit(0);
+    }
+}
+
+/// Names of the arguments of `entry_filter_args`.
+const ENTRY_FILTER_ARGS: &[&str] = &[
+    "fetchfilter",
+    "tag",
+    "tag_regex",
+    "benchmark",
+    "since",
+    "until",
+    "host",
+    "commit",
+    "branch",
+    "user",
+    "run_id",
+    "meta",
+];
+
+fn entry_filter_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::from_usage(
+            "[fetchfilter], --fetchfilter=[REGEXP] 'Filters executables with a mongodb compatible regexp'",
+        )
+        .default_value(".*"),
+        Arg::from_usage("[tag], --tag=[TAG]... 'Only use results with this tag, can be repeated'")
+            .number_of_values(1),
+        Arg::from_usage("[tag_regex], --tag-regex=[REGEXP] 'Only use results with a tag matching the regexp'"),
+        Arg::from_usage("[benchmark], --benchmark=[REGEXP] 'Only use single benchmarks with a name matching the regexp'"),
+        Arg::from_usage("[since], --since=[DATE] 'Only use results from this date on, e.g. 2021-03-01'"),
+        Arg::from_usage("[until], --until=[DATE] 'Only use results up to this date (inclusive), e.g. 2021-03-31'"),
+        Arg::from_usage("[host], --host=[REGEXP] 'Only use results from hosts matching the regexp'"),
+        Arg::from_usage("[commit], --commit=[SHA] 'Only use results built from the git commit (prefix)'"),
+        Arg::from_usage("[branch], --branch=[REGEXP] 'Only use results built from git branches matching the regexp'"),
+        Arg::from_usage("[user], --user=[REGEXP] 'Only use results pushed by users matching the regexp'"),
+        Arg::from_usage("[run_id], --run-id=[ID] 'Only use results of the run with this ID'"),
+        Arg::from_usage("[meta], --meta=[KEY=VALUE]... 'Only use results with this metadata, can be repeated'")
+            .number_of_values(1),
+    ]
+}
+
+fn entry_filter_from_matches(submatches: &ArgMatches) -> EntryFilter {
+    let mut filters = vec![EntryFilter::ExeName(
+        submatches
+            .value_of("fetchfilter")
+            .unwrap_or(".*")
+            .to_string(),
+    )];
+    for tag in values_of(submatches, "tag") {
+        filters.push(EntryFilter::Tag(tag));
+    }
+    for (key, value) in meta_from_matches(submatches) {
+        filters.push(EntryFilter::Meta(key, value));
+    }
+    if let Some(tag_regex) = submatches.value_of("tag_regex") {
+        filters.push(EntryFilter::TagRegex(tag_regex.to_string()));
+    }
+    if let Some(benchmark) = submatches.value_of("benchmark") {
+        filters.push(EntryFilter::Benchmark(benchmark.to_string()));
+    }
+    if let Some(since) = submatches.value_of("since") {
+        filters.push(EntryFilter::Since(since.to_string()));
+    }
+    if let Some(until) = submatches.value_of("until") {
+        filters.push(EntryFilter::Until(until.to_string()));
+    }
+    if let Some(host) = submatches.value_of("host") {
+        filters.push(EntryFilter::Host(host.to_string()));
+    }
+    if let Some(commit) = submatches.value_of("commit") {
+        filters.push(EntryFilter::Provenance(
+            ProvenanceField::GitCommit,
+            format!("^{}", regex::escape(commit)),
+        ));
+    }
+    if let Some(branch) = submatches.value_of("branch") {
+        filters.push(EntryFilter::Provenance(
+            ProvenanceField::GitBranch,
+            branch.to_string(),
+        ));
+    }
+    if let Some(user) = submatches.value_of("user") {
+        filters.push(EntryFilter::Provenance(
+            ProvenanceField::User,
+            user.to_string(),
+        ));
+    }
+    if let Some(run_id) = submatches.value_of("run_id") {
+        filters.push(EntryFilter::Provenance(
+            ProvenanceField::RunId,
+            format!("^{}$", regex::escape(run_id)),
+        ));
+    }
+    EntryFilter::All(filters)
+}
+
+fn bisect_settings_from_matches(submatches: &ArgMatches) -> repocheck::BisectSettings {
+    let benchmark = submatches.value_of("bisect_benchmark").unwrap();
+    let benchmark_regex = match regex::Regex::new(benchmark) {
+        Ok(regex) => regex,
+        Err(e) => error_and_exit("Invalid value for --benchmark", &e),
+    };
+    let threshold = submatches.value_of("bisect_threshold").unwrap_or("10%");
+    let threshold_percent = match threshold.trim_end_matches('%').parse::<f64>() {
+        Ok(threshold_percent) => threshold_percent,
+        Err(e) => error_and_exit("Invalid value for --threshold", &e),
+    };
+    let confirm_repetitions = submatches
+        .value_of("confirm_repetitions")
+        .map(|repetitions| match repetitions.parse::<u32>() {
+            Ok(repetitions) => repetitions,
+            Err(e) => error_and_exit("Invalid value for --confirm", &e),
+        });
+    repocheck::BisectSettings {
+        benchmark_regex,
+        threshold_percent,
+        confirm_repetitions,
+    }
+}
+
+fn confirmation_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("dry_run")
+            .help("Only list the results which would be removed")
+            .long("dry-run"),
+        Arg::with_name("yes")
+            .help("Remove the results without asking for confirmation")
+            .long("yes"),
+    ]
+}
+
+/// Lists all entries matching the filter and deletes them after confirmation.
+fn delete_with_confirmation(
+    storage: &dyn Storage,
+    entry_filter: &EntryFilter,
+    submatches: &ArgMatches,
+) {
+    let matching_entries = storage.fetch(entry_filter, None);
+    let matching_count = matching_entries.len();
+    println!("Results to remove:");
+    for entry in matching_entries {
+        println!(
+            "{} {} [{}]",
+            entry.results.context.date,
+            entry.exe_name,
+            entry.tags.join(", ")
+        );
+    }
+
+    if matching_count == 0 {
+        println!("Did not find any matching results. Nothing to remove!");
+        return;
+    }
+    if submatches.is_present("dry_run") {
+        println!("Dry run: {} results would be removed.", matching_count);
+        return;
+    }
+    if !submatches.is_present("yes") && !confirm(&format!("Remove {} results?", matching_count)) {
+        println!("Aborted.");
+        return;
+    }
+
+    let deleted_count = storage.delete(entry_filter);
+    println!("Removed {} results.", deleted_count);
+}
+
+fn confirm(question: &str) -> bool {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush().unwrap();
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .expect("Could not read answer!");
+    matches!(answer.trim(), "y" | "Y" | "yes")
+}
+
+fn values_of(submatches: &ArgMatches, name: &str) -> Vec<String> {
+    submatches
+        .values_of(name)
+        .map(|values| values.map(String::from).collect())
+        .unwrap_or_default()
+}
+
+fn meta_from_matches(submatches: &ArgMatches) -> BTreeMap<String, String> {
+    let key_regex = regex::Regex::new("^[A-Za-z0-9_-]+$").unwrap();
+    let mut meta = BTreeMap::new();
+    for key_value in values_of(submatches, "meta") {
+        match key_value.split_once('=') {
+            Some((key, value)) if key_regex.is_match(key) => {
+                meta.insert(key.to_string(), value.to_string());
+            }
+            _ => error_and_exit(
+                "Metadata must be given as KEY=VALUE with KEY consisting of [A-Za-z0-9_-]",
+                &std::io::Error::new(std::io::ErrorKind::InvalidInput, key_value),
+            ),
+        }
+    }
+    meta
+}
+
 fn print_config_not_set() {
     println!("database config is not yet set. Use 'beast config' for this.");
 }
@@ -1,5 +1,9 @@
-use serde::{Deserialize, Serialize};
+use crate::provenance::*;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_json::json;
+use std::collections::BTreeMap;
 use std::env;
 use std::fs::File;
 use std::io::BufReader;
@@ -7,29 +11,40 @@ use std::path::{Path, PathBuf};
 
 const LAST_RESULTS_FILENAME: &str = "beast_temp_lastresults.json";
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct DataBaseEntry {
     pub exe_name: String,
-    pub tag: String,
+    // entries pushed by previous versions only have a single "tag"
+    #[serde(default, alias = "tag", deserialize_with = "deserialize_tags")]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub meta: BTreeMap<String, String>,
+    pub provenance: Option<Provenance>,
     pub results: BenchmarkResults,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BenchmarkResults {
     pub commit: Option<String>,
+    pub run_id: Option<String>,
+    /// Collected when the benchmarks ran, moved to the entry when pushing.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provenance: Option<Provenance>,
     pub context: BenchmarkContext,
     pub benchmarks: Vec<BenchmarkResult>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BenchmarkContext {
     pub date: String,
     pub executable: PathBuf,
     pub num_cpus: i32,
     pub mhz_per_cpu: i32,
+    pub host_name: Option<String>,
+    pub library_build_type: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BenchmarkResult {
     pub name: String,
     pub iterations: i32,
@@ -38,6 +53,23 @@ pub struct BenchmarkResult {
     pub time_unit: Option<String>,
 }
 
+pub fn deserialize_tags<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum TagOrTags {
+        Tag(String),
+        Tags(Vec<String>),
+    }
+
+    Ok(match TagOrTags::deserialize(deserializer)? {
+        TagOrTags::Tag(tag) if tag.is_empty() => vec![],
+        TagOrTags::Tag(tag) => vec![tag],
+        TagOrTags::Tags(tags) => tags,
+    })
+}
+
 pub fn parse_single_benchmark_file<P: AsRef<Path>>(file_path: P) -> BenchmarkResults {
     let json = json_from_file(file_path);
     let bm_context = json.get("context").unwrap();
@@ -45,6 +77,8 @@ pub fn parse_single_benchmark_file<P: AsRef<Path>>(file_path: P) -> BenchmarkRes
 
     let mut results = BenchmarkResults {
         commit: Some("".to_string()),
+        run_id: None,
+        provenance: None,
         context: serde_json::from_value(bm_context.clone()).unwrap(),
         benchmarks: Vec::new(),
     };
@@ -55,7 +89,7 @@ pub fn parse_single_benchmark_file<P: AsRef<Path>>(file_path: P) -> BenchmarkRes
         results.benchmarks.push(bm_result);
     }
 
-    return results;
+    results
 }
 
 pub fn parse_cumulated_benchmark_file() -> Vec<BenchmarkResults> {
@@ -65,8 +99,13 @@ pub fn parse_cumulated_benchmark_file() -> Vec<BenchmarkResults> {
         std::process::exit(0);
     }
     let cumulated_results = json_from_file(file_path);
-    return serde_json::from_value(cumulated_results)
-        .expect("Could not deserialize JsonValue from cumulated benchmark file!");
+    serde_json::from_value(cumulated_results)
+        .expect("Could not deserialize JsonValue from cumulated benchmark file!")
+}
+
+pub fn parse_results_file<P: AsRef<Path>>(file_path: P) -> Vec<BenchmarkResults> {
+    let results = json_from_file(file_path);
+    serde_json::from_value(results).expect("Could not deserialize JsonValue from results file!")
 }
 
 pub fn export_cumulated_results(cumulated_results: &Vec<BenchmarkResults>) {
@@ -74,36 +113,50 @@ pub fn export_cumulated_results(cumulated_results: &Vec<BenchmarkResults>) {
     let export_file_path_str = export_file.as_path().to_string_lossy();
 
     let f = File::create(&export_file)
-        .expect(&format!("Could not create file {}!", export_file_path_str));
+        .unwrap_or_else(|_| panic!("Could not create file {}!", export_file_path_str));
     let results_json_val = json!(*cumulated_results);
 
-    serde_json::to_writer(&f, &results_json_val).expect(&format!(
-        "Could not write to file {}!",
-        export_file_path_str
-    ));
+    serde_json::to_writer(&f, &results_json_val)
+        .unwrap_or_else(|_| panic!("Could not write to file {}!", export_file_path_str));
 }
 
-pub fn export_results_to_file(results: &Vec<BenchmarkResults>, file_path: &Path) {
-    let f = File::create(file_path).expect(&format!(
-        "Could not create file {}!",
-        file_path.to_string_lossy()
-    ));
+pub fn export_results_to_file(
+    results: &Vec<BenchmarkResults>,
+    file_path: &Path,
+) -> std::io::Result<()> {
+    let f = File::create(file_path)?;
     let results_json_val = json!(*results);
 
-    serde_json::to_writer(&f, &results_json_val).expect(&format!(
-        "Could not write to file {}!",
-        file_path.to_string_lossy()
-    ));
+    serde_json::to_writer(&f, &results_json_val)?;
+    Ok(())
 }
 
 pub fn json_from_file<P: AsRef<Path>>(file_path: P) -> serde_json::Value {
     let result_file = File::open(file_path).expect("Benchmark result file not found!");
     let reader = BufReader::new(result_file);
-    return serde_json::from_reader(reader).expect("JSON was not well-formatted!");
+    serde_json::from_reader(reader).expect("JSON was not well-formatted!")
 }
 
 fn exported_results_file_path() -> PathBuf {
     let mut temp_dir = env::temp_dir();
     temp_dir.push(LAST_RESULTS_FILENAME);
-    return temp_dir;
+    temp_dir
+}
+
+/// Parses the date of a benchmark context, dates without timezone are taken as UTC.
+pub fn parse_timestamp(date: &str) -> Option<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(date) {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    if let Ok(timestamp) = DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%z") {
+        return Some(timestamp.with_timezone(&Utc));
+    }
+    [
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M:%S",
+        "%m/%d/%y %H:%M:%S",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(date, format).ok())
+    .map(|timestamp| Utc.from_utc_datetime(&timestamp))
 }
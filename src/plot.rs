@@ -1,9 +1,11 @@
 use crate::parse::*;
+use crate::resample::*;
 
+use chrono::{DateTime, Utc};
 use plotly::common::{DashType, Line, LineShape, Mode, Title};
-use plotly::layout::{Axis, BarMode, Layout};
+use plotly::layout::{Axis, AxisType, BarMode, Layout};
 use plotly::{Bar, Plot, Scatter};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 
 pub fn plot_all_as_bars(all_results: &Vec<BenchmarkResults>, plot_time_unit: &str) {
@@ -73,7 +75,7 @@ pub fn plot_all_as_lines(all_results: &Vec<BenchmarkResults>, plot_time_unit: &s
 
             x_values
                 .entry(sub_bm_name.to_string())
-                .or_insert(Vec::new())
+                .or_default()
                 .push(sub_bm_x_val);
 
             let sub_bm_duration =
@@ -82,7 +84,7 @@ pub fn plot_all_as_lines(all_results: &Vec<BenchmarkResults>, plot_time_unit: &s
 
             y_values
                 .entry(sub_bm_name.to_string())
-                .or_insert(Vec::new())
+                .or_default()
                 .push(sub_bm_converted_cpu_time);
         }
 
@@ -106,41 +108,40 @@ pub fn plot_all_as_lines(all_results: &Vec<BenchmarkResults>, plot_time_unit: &s
 }
 
 // TODO: refine plot options, e.g. include means and variances in chart
-pub fn plot_all_as_commit_series(results: &Vec<BenchmarkResults>, plot_time_unit: &str) {
-    let mut xlabels: HashMap<String, Vec<String>> = HashMap::new();
-    let mut cpu_times: HashMap<String, Vec<f64>> = HashMap::new();
-    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+/// Plots the results over all `commits` in the given order. Commits without results, e.g.
+/// because their build failed, are shown as gaps.
+pub fn plot_all_as_commit_series(
+    results: &[BenchmarkResults],
+    commits: &[String],
+    plot_time_unit: &str,
+) {
+    let mut cpu_times: BTreeMap<String, HashMap<String, f64>> = BTreeMap::new();
 
     // collect benchmark data per commit for each "exename_benchmarkname"
     for result in results {
         for benchmark in &result.benchmarks {
             let exe_name = result.context.executable.file_name().unwrap();
-            let trace_name = exe_name.to_string_lossy().to_owned() + "_" + benchmark.name.as_str();
-            let trace_name = trace_name.to_string();
-
-            // build current xlabel
-            let commit_id = result.commit.as_ref().unwrap();
-            let xlabel = build_label(commit_id.as_str(), "");
+            let trace_name =
+                exe_name.to_string_lossy().into_owned() + "_" + benchmark.name.as_str();
 
             // set cpu_time based on time unit for plot
             let cpu_time_as_duration =
                 from_benchmark_time(benchmark.time_unit.as_ref(), benchmark.cpu_time as u64);
             let converted_cpu_time = convert_time_to_unit(cpu_time_as_duration, plot_time_unit);
 
-            xlabels
-                .entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(xlabel);
+            let commit_id = result.commit.as_ref().unwrap();
             cpu_times
-                .entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(converted_cpu_time);
-            tags.entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(commit_id.clone());
+                .entry(trace_name)
+                .or_default()
+                .insert(commit_id.clone(), converted_cpu_time);
         }
     }
 
+    let xlabels: Vec<String> = commits
+        .iter()
+        .map(|commit_id| build_label(commit_id.as_str(), ""))
+        .collect();
+
     // create plot
     let y_title = format!("CPU runtime [{}]", plot_time_unit).to_string();
 
@@ -153,11 +154,15 @@ pub fn plot_all_as_commit_series(results: &Vec<BenchmarkResults>, plot_time_unit
 
     plot.set_layout(layout);
 
-    for trace_name in xlabels.keys() {
-        let trace = Scatter::new(xlabels[trace_name].clone(), cpu_times[trace_name].clone())
+    for (trace_name, commit_cpu_times) in &cpu_times {
+        let trace_cpu_times: Vec<Option<f64>> = commits
+            .iter()
+            .map(|commit_id| commit_cpu_times.get(commit_id).copied())
+            .collect();
+        let trace = Scatter::new(xlabels.clone(), trace_cpu_times)
             .mode(Mode::LinesMarkers)
             .name(trace_name)
-            .text_array(tags[trace_name].clone())
+            .text_array(commits.to_vec())
             .line(Line::new().shape(LineShape::Hv));
 
         plot.add_trace(trace);
@@ -166,46 +171,200 @@ pub fn plot_all_as_commit_series(results: &Vec<BenchmarkResults>, plot_time_unit
     plot.show();
 }
 
-pub fn plot_db_entries(db_entries: &Vec<DataBaseEntry>, plot_time_unit: &str) {
-    let mut xlabels: HashMap<String, Vec<String>> = HashMap::new();
-    let mut cpu_times: HashMap<String, Vec<f64>> = HashMap::new();
-    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+pub const PLOT_X_AXES: &[&str] = &["date", "commit", "tag"];
+
+/// Quantity on the x axis of time series plots of stored results.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PlotXAxis {
+    Date,
+    Commit,
+    Tag,
+}
+
+impl PlotXAxis {
+    pub fn from_name(name: &str) -> Option<PlotXAxis> {
+        match name {
+            "date" => Some(PlotXAxis::Date),
+            "commit" => Some(PlotXAxis::Commit),
+            "tag" => Some(PlotXAxis::Tag),
+            _ => None,
+        }
+    }
+}
+
+/// Single point of a trace, the timestamp orders the points of every trace.
+#[derive(Debug)]
+struct SeriesPoint {
+    timestamp: DateTime<Utc>,
+    xlabel: String,
+    cpu_time: f64,
+    text: String,
+}
+
+pub fn plot_db_entries(db_entries: &[DataBaseEntry], plot_time_unit: &str, x_axis: PlotXAxis) {
+    let series = db_entry_series(db_entries, plot_time_unit, x_axis);
+
+    // create plot
+    let y_title = format!("CPU runtime [{}]", plot_time_unit).to_string();
+
+    // commit IDs and tags must not be interpreted as numbers or dates
+    let x_axis = match x_axis {
+        PlotXAxis::Date => Axis::new()
+            .title(Title::from("Date (UTC)"))
+            .type_(AxisType::Date),
+        PlotXAxis::Commit => Axis::new()
+            .title(Title::from("Commit"))
+            .type_(AxisType::Category),
+        PlotXAxis::Tag => Axis::new()
+            .title(Title::from("Tag"))
+            .type_(AxisType::Category),
+    };
+
+    let layout = Layout::new()
+        .title(Title::from("Benchmark results over time"))
+        .x_axis(x_axis.auto_margin(true))
+        .y_axis(Axis::new().title(Title::from(y_title.as_str())));
+
+    let mut plot = Plot::new();
+
+    plot.set_layout(layout);
+
+    for (trace_name, points) in series {
+        let xlabels: Vec<String> = points.iter().map(|point| point.xlabel.clone()).collect();
+        let cpu_times: Vec<f64> = points.iter().map(|point| point.cpu_time).collect();
+        let texts: Vec<String> = points.iter().map(|point| point.text.clone()).collect();
+        let trace = Scatter::new(xlabels, cpu_times)
+            .mode(Mode::LinesMarkers)
+            .name(&trace_name)
+            .text_array(texts)
+            .line(Line::new().shape(LineShape::Hv));
+
+        plot.add_trace(trace);
+    }
+
+    plot.show();
+}
+
+/// Collects the points of each "exename_benchmarkname" trace ordered by time. Entries
+/// without a parsable date or without a value for the x axis are left out.
+fn db_entry_series(
+    db_entries: &[DataBaseEntry],
+    plot_time_unit: &str,
+    x_axis: PlotXAxis,
+) -> BTreeMap<String, Vec<SeriesPoint>> {
+    let mut series: BTreeMap<String, Vec<SeriesPoint>> = BTreeMap::new();
+    let mut skipped_count = 0;
 
-    // collect time series data for each "exename_benchmarkname"
     for db_entry in db_entries {
+        let timestamp = match parse_timestamp(&db_entry.results.context.date) {
+            Some(timestamp) => timestamp,
+            None => {
+                skipped_count += 1;
+                continue;
+            }
+        };
+
+        // results of a single benchmark file have an empty commit
+        let commit = db_entry
+            .results
+            .commit
+            .as_ref()
+            .filter(|commit| !commit.is_empty())
+            .or(db_entry
+                .provenance
+                .as_ref()
+                .and_then(|provenance| provenance.git_commit.as_ref()));
+        let xlabel = match x_axis {
+            PlotXAxis::Date => timestamp.format("%Y-%m-%d %H:%M:%S").to_string(),
+            PlotXAxis::Commit => match commit {
+                Some(commit) => commit.clone(),
+                None => {
+                    skipped_count += 1;
+                    continue;
+                }
+            },
+            PlotXAxis::Tag => match db_entry.tags.is_empty() {
+                false => db_entry.tags.join(", "),
+                true => {
+                    skipped_count += 1;
+                    continue;
+                }
+            },
+        };
+        let text = match x_axis {
+            PlotXAxis::Date => db_entry.tags.join(", "),
+            PlotXAxis::Commit | PlotXAxis::Tag => db_entry.results.context.date.clone(),
+        };
+
         for single_result in &db_entry.results.benchmarks {
             let trace_name = db_entry.exe_name.clone() + "_" + single_result.name.as_str();
 
-            // build current xlabel
-            let xlabel = build_label(db_entry.results.context.date.as_str(), "");
-
             // set cpu_time based on time unit for plot
-            let cpu_time_as_duration = from_benchmark_time(
-                single_result.time_unit.as_ref(),
-                single_result.cpu_time as u64,
-            );
-            let converted_cpu_time = convert_time_to_unit(cpu_time_as_duration, plot_time_unit);
-
-            xlabels
-                .entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(xlabel);
-            cpu_times
-                .entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(converted_cpu_time);
-            tags.entry(trace_name.clone())
-                .or_insert(Vec::new())
-                .push(db_entry.tag.clone());
+            let cpu_time_ns = to_nanos(single_result.cpu_time, single_result.time_unit.as_deref());
+
+            series.entry(trace_name).or_default().push(SeriesPoint {
+                timestamp,
+                xlabel: xlabel.clone(),
+                cpu_time: from_nanos(cpu_time_ns, plot_time_unit),
+                text: text.clone(),
+            });
         }
     }
 
+    if skipped_count > 0 {
+        println!(
+            "Skipped {} results without date or x axis value!",
+            skipped_count
+        );
+    }
+
+    for points in series.values_mut() {
+        points.sort_by_key(|point| point.timestamp);
+    }
+    series
+}
+
+pub fn plot_resampled_points(
+    points: &[ResampledPoint],
+    statistic: ResampleStatistic,
+    plot_time_unit: &str,
+) {
+    // ordered maps keep the trace order stable between plots
+    let mut xlabels: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut cpu_times: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    let mut texts: BTreeMap<String, Vec<String>> = BTreeMap::new();
+
+    // points are ordered by period, so every trace is ordered as well
+    for point in points {
+        let trace_name = point.exe_name.clone() + "_" + point.benchmark_name.as_str();
+        let cpu_time = from_nanos(point.cpu_time_ns, plot_time_unit);
+
+        xlabels
+            .entry(trace_name.clone())
+            .or_default()
+            .push(point.period_start.clone());
+        cpu_times
+            .entry(trace_name.clone())
+            .or_default()
+            .push(cpu_time);
+        texts.entry(trace_name).or_default().push(format!(
+            "{} of {} results",
+            statistic.name(),
+            point.sample_count
+        ));
+    }
+
     // create plot
-    let y_title = format!("CPU runtime [{}]", plot_time_unit).to_string();
+    let y_title = format!("CPU runtime ({}) [{}]", statistic.name(), plot_time_unit);
 
     let layout = Layout::new()
-        .title(Title::from("Benchmark results over time"))
-        .x_axis(Axis::new().title(Title::from("Date")).auto_margin(true))
+        .title(Title::from("Resampled benchmark results over time"))
+        .x_axis(
+            Axis::new()
+                .title(Title::from("Period"))
+                .type_(AxisType::Date)
+                .auto_margin(true),
+        )
         .y_axis(Axis::new().title(Title::from(y_title.as_str())));
 
     let mut plot = Plot::new();
@@ -216,7 +375,7 @@ pub fn plot_db_entries(db_entries: &Vec<DataBaseEntry>, plot_time_unit: &str) {
         let trace = Scatter::new(xlabels[trace_name].clone(), cpu_times[trace_name].clone())
             .mode(Mode::LinesMarkers)
             .name(trace_name)
-            .text_array(tags[trace_name].clone())
+            .text_array(texts[trace_name].clone())
             .line(Line::new().shape(LineShape::Hv));
 
         plot.add_trace(trace);
@@ -260,9 +419,66 @@ fn build_label(date_time: &str, tag: &str) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::provenance::Provenance;
+    use crate::storage::tests::test_entry;
+
     #[test]
     fn test_build_label_function() {
         assert_eq!(build_label("Test", "123"), "Test (123)");
         assert_eq!(build_label("Test", ""), "Test");
     }
+
+    #[test]
+    fn test_db_entry_series_ordered_by_time() {
+        // earlier in UTC, although the date string is greater
+        let mut earlier_entry = test_entry("simple_benchmark", "v1");
+        earlier_entry.results.context.date = "2021-01-02T01:00:00+02:00".to_string();
+        earlier_entry.results.commit = Some("aaaaaaaa".to_string());
+        let mut later_entry = test_entry("simple_benchmark", "v2");
+        later_entry.results.context.date = "2021-01-01T20:00:00-06:00".to_string();
+        later_entry.results.commit = Some("bbbbbbbb".to_string());
+        let entries = vec![
+            later_entry,
+            earlier_entry,
+            test_entry("simple_benchmark", ""),
+        ];
+
+        let series = db_entry_series(&entries, "ns", PlotXAxis::Date);
+        let xlabels: Vec<&str> = series["simple_benchmark_BM_Foo"]
+            .iter()
+            .map(|point| point.xlabel.as_str())
+            .collect();
+        assert_eq!(
+            xlabels,
+            vec![
+                "2021-01-01 00:00:00",
+                "2021-01-01 23:00:00",
+                "2021-01-02 02:00:00"
+            ]
+        );
+
+        let series = db_entry_series(&entries, "ns", PlotXAxis::Commit);
+        assert_eq!(series["simple_benchmark_BM_Bar"].len(), 2);
+        assert_eq!(series["simple_benchmark_BM_Bar"][0].xlabel, "aaaaaaaa");
+        assert_eq!(
+            series["simple_benchmark_BM_Bar"][1].text,
+            entries[0].results.context.date
+        );
+    }
+
+    #[test]
+    fn test_db_entry_series_commit_from_provenance() {
+        let mut entry = test_entry("simple_benchmark", "");
+        // as parsed from a single benchmark file
+        entry.results.commit = Some(String::new());
+        entry.results.benchmarks[0].cpu_time = 1.5;
+        entry.provenance = Some(Provenance {
+            git_commit: Some("cccccccc".to_string()),
+            ..Default::default()
+        });
+
+        let series = db_entry_series(&[entry], "ns", PlotXAxis::Commit);
+        assert_eq!(series["simple_benchmark_BM_Foo"][0].xlabel, "cccccccc");
+        assert_eq!(series["simple_benchmark_BM_Foo"][0].cpu_time, 1.5);
+    }
 }
@@ -0,0 +1,117 @@
+use clap::crate_version;
+use git2::{Repository, StatusOptions};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::path::Path;
+
+/// Describes where a pushed result comes from.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Provenance {
+    pub run_id: Option<String>,
+    pub git_commit: Option<String>,
+    pub git_branch: Option<String>,
+    pub git_dirty: Option<bool>,
+    pub host_name: Option<String>,
+    pub os: String,
+    pub user: Option<String>,
+    pub beast_version: String,
+    pub ci_build_id: Option<String>,
+    pub ci_job_url: Option<String>,
+}
+
+/// Provenance fields which can be used in entry filters.
+pub enum ProvenanceField {
+    RunId,
+    GitCommit,
+    GitBranch,
+    User,
+}
+
+impl ProvenanceField {
+    pub fn key(&self) -> &'static str {
+        match self {
+            ProvenanceField::RunId => "run_id",
+            ProvenanceField::GitCommit => "git_commit",
+            ProvenanceField::GitBranch => "git_branch",
+            ProvenanceField::User => "user",
+        }
+    }
+}
+
+impl Provenance {
+    /// Collects the provenance of a benchmark executable, git information is taken from
+    /// the repository containing the executable (e.g. the build directory in the source tree).
+    pub fn collect(executable: &Path, run_id: Option<String>) -> Provenance {
+        let mut provenance = Provenance {
+            run_id,
+            host_name: hostname::get()
+                .ok()
+                .map(|host_name| host_name.to_string_lossy().into_owned()),
+            os: env::consts::OS.to_string(),
+            user: env::var("USER").or_else(|_| env::var("USERNAME")).ok(),
+            beast_version: crate_version!().to_string(),
+            ci_build_id: ci_build_id(),
+            ci_job_url: ci_job_url(),
+            ..Default::default()
+        };
+
+        let source_dir = executable.parent().unwrap_or(executable);
+        if let Ok(repo) = Repository::discover(source_dir) {
+            if let Ok(head) = repo.head() {
+                provenance.git_commit = head.peel_to_commit().ok().map(|c| c.id().to_string());
+                if head.is_branch() {
+                    provenance.git_branch = head.shorthand().map(String::from);
+                }
+            }
+            let mut status_options = StatusOptions::new();
+            status_options.include_untracked(false);
+            provenance.git_dirty = repo
+                .statuses(Some(&mut status_options))
+                .ok()
+                .map(|statuses| !statuses.is_empty());
+        }
+
+        provenance
+    }
+
+    pub fn value(&self, field: &ProvenanceField) -> Option<&str> {
+        match field {
+            ProvenanceField::RunId => self.run_id.as_deref(),
+            ProvenanceField::GitCommit => self.git_commit.as_deref(),
+            ProvenanceField::GitBranch => self.git_branch.as_deref(),
+            ProvenanceField::User => self.user.as_deref(),
+        }
+    }
+}
+
+// GitHub Actions, GitLab CI, Jenkins, Azure Pipelines, CircleCI, Travis CI
+fn ci_build_id() -> Option<String> {
+    [
+        "GITHUB_RUN_ID",
+        "CI_PIPELINE_ID",
+        "BUILD_ID",
+        "BUILD_BUILDID",
+        "CIRCLE_BUILD_NUM",
+        "TRAVIS_BUILD_ID",
+    ]
+    .iter()
+    .find_map(|var| env::var(var).ok())
+}
+
+fn ci_job_url() -> Option<String> {
+    if let (Ok(server), Ok(repo), Ok(run_id)) = (
+        env::var("GITHUB_SERVER_URL"),
+        env::var("GITHUB_REPOSITORY"),
+        env::var("GITHUB_RUN_ID"),
+    ) {
+        return Some(format!("{}/{}/actions/runs/{}", server, repo, run_id));
+    }
+    [
+        "CI_JOB_URL",
+        "BUILD_URL",
+        "CIRCLE_BUILD_URL",
+        "TRAVIS_BUILD_WEB_URL",
+    ]
+    .iter()
+    .find_map(|var| env::var(var).ok())
+}
@@ -1,16 +1,25 @@
+use crate::config::*;
 use crate::exec::*;
 use crate::find::*;
 use crate::logger::*;
 use crate::parse::*;
+use crate::report::*;
+use crate::spool::*;
+use crate::storage::*;
 
 use colored::*;
 use execute::{shell, Execute};
-use git2::{Commit, Error, Oid, Repository};
+use git2::{BranchType, Commit, Error, Oid, Repository, Worktree, WorktreePruneOptions};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::Write;
 use std::fs::{self, File};
 use std::io::BufReader;
 use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct RepocheckSettings {
@@ -22,6 +31,66 @@ pub struct RepocheckSettings {
     build_commands: String,
     benchmark_regex: String,
     pub no_clean: Option<bool>, // special flag to e.g. continue from previous run
+    pub push: Option<bool>,     // push the results of every commit to the configured database
+    pub keep_worktree: Option<bool>, // keep the temporary worktree, e.g. to debug the build
+    pub on_build_failure: Option<BuildFailureAction>, // skip (default) or abort at a broken build
+}
+
+/// File name suffix of the build logs of failed commits in the results directory.
+const BUILD_LOG_SUFFIX: &str = ".build_failed.log";
+
+pub const BUILD_FAILURE_ACTIONS: &[&str] = &["skip", "abort"];
+
+/// What to do if a build command of a commit fails. Skipped commits are recorded with their
+/// build log, so they show up as gaps in the plot instead of stale results.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum BuildFailureAction {
+    Skip,
+    Abort,
+}
+
+impl BuildFailureAction {
+    pub fn from_name(name: &str) -> Option<BuildFailureAction> {
+        match name {
+            "skip" => Some(BuildFailureAction::Skip),
+            "abort" => Some(BuildFailureAction::Abort),
+            _ => None,
+        }
+    }
+}
+
+/// Results of all benchmarked commits of a previous run.
+pub struct RepocheckResults {
+    /// Short IDs of the commits in the range, oldest first
+    pub commits: Vec<String>,
+    pub results: Vec<BenchmarkResults>,
+    /// Short IDs of the commits whose build failed
+    pub failed_commits: Vec<String>,
+}
+
+/// Outcome of building and benchmarking a single commit.
+enum CommitRun {
+    Benchmarked(Vec<BenchmarkResults>),
+    BuildFailed(BuildFailure),
+}
+
+/// The first failed build command of a commit with the log of all its build commands.
+struct BuildFailure {
+    command: String,
+    status: ExitStatus,
+    log: String,
+}
+
+/// Set by SIGINT/SIGTERM, repocheck stops at the next step and removes its worktree.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Options of `beast repocheck --bisect`.
+pub struct BisectSettings {
+    pub benchmark_regex: Regex,
+    pub threshold_percent: f64,
+    /// Repetitions to confirm the found regression with, none to skip the confirmation
+    pub confirm_repetitions: Option<u32>,
 }
 
 pub fn parse<P: AsRef<Path>>(yaml_path: P) -> RepocheckSettings {
@@ -36,7 +105,7 @@ pub fn parse<P: AsRef<Path>>(yaml_path: P) -> RepocheckSettings {
     }
 }
 
-pub fn collect_repocheck_results(settings: &RepocheckSettings) -> Vec<BenchmarkResults> {
+pub fn collect_repocheck_results(settings: &RepocheckSettings) -> RepocheckResults {
     let full_repo_path = match std::fs::canonicalize(settings.repo_path.as_path()) {
         Ok(path) => path,
         Err(e) => {
@@ -47,10 +116,19 @@ pub fn collect_repocheck_results(settings: &RepocheckSettings) -> Vec<BenchmarkR
     let export_dir = export_dir(&full_repo_path, &settings.branch_name);
 
     let mut collected_benchmark_results: Vec<BenchmarkResults> = vec![];
+    let mut failed_commits = vec![];
     if export_dir.is_dir() {
         println!("Files to parse:");
         for entry in fs::read_dir(export_dir).unwrap() {
             let repocheck_file_path = entry.unwrap().path();
+            let file_name = repocheck_file_path.file_name().unwrap().to_string_lossy();
+            if let Some(commit_id_str) = file_name
+                .strip_prefix("commit_")
+                .and_then(|name| name.strip_suffix(BUILD_LOG_SUFFIX))
+            {
+                failed_commits.push(commit_id_str.to_string());
+                continue;
+            }
             println!("{}", &repocheck_file_path.to_string_lossy());
             let single_file_results = json_from_file(repocheck_file_path.as_path());
             let mut json: Vec<BenchmarkResults> = serde_json::from_value(single_file_results)
@@ -58,38 +136,50 @@ pub fn collect_repocheck_results(settings: &RepocheckSettings) -> Vec<BenchmarkR
             collected_benchmark_results.append(&mut json);
         }
     }
-    collected_benchmark_results
-}
-
-pub fn run(settings: &RepocheckSettings) {
-    let full_repo_path = match std::fs::canonicalize(settings.repo_path.as_path()) {
-        Ok(path) => path,
-        Err(e) => {
-            error_and_exit("Invalid path to repository", &e);
-        }
-    };
 
-    let repo = match Repository::open(full_repo_path.as_path()) {
-        Ok(repo) => repo,
+    // order the commits like the history, results of missing commits are plotted as gaps
+    let commits = match Repository::open(full_repo_path.as_path())
+        .and_then(|repo| commit_range(&repo, settings))
+    {
+        Ok(oids) => oids.iter().map(|oid| id_to_str(oid.as_bytes())).collect(),
         Err(e) => {
-            error_and_exit("Could not open repository", &e);
+            warn(
+                "Could not read commit range, plotting commits in file order",
+                &e,
+            );
+            let mut commits: Vec<String> = vec![];
+            for commit in collected_benchmark_results
+                .iter()
+                .filter_map(|result| result.commit.clone())
+            {
+                if !commits.contains(&commit) {
+                    commits.push(commit);
+                }
+            }
+            commits
         }
     };
 
-    if repo.state() != git2::RepositoryState::Clean {
-        let e = Error::from_str("RepositoryState != Clean");
-        error_and_exit("Clean up repository state first!", &e);
+    RepocheckResults {
+        commits,
+        results: collected_benchmark_results,
+        failed_commits,
     }
+}
 
-    println!(
-        "Checking out branch '{}' in repository '{}'...",
-        settings.branch_name,
-        full_repo_path.to_string_lossy()
-    );
-    if let Err(e) = checkout_branch(&repo, &settings.branch_name) {
-        error_and_exit("Could not checkout specified branch", &e);
+pub fn run(settings: &RepocheckSettings, config: &AppConfig) {
+    if let Some(true) = settings.push {
+        ensure_pushable(config);
+    }
+    if let Err(e) = run_in_worktree(settings, config) {
+        error_and_exit("Could not walk through specified commit range", &e);
     }
     println!("{}\n", "Successful!".green());
+}
+
+/// Errors are returned instead of exiting, so the worktree is removed in any case.
+fn run_in_worktree(settings: &RepocheckSettings, config: &AppConfig) -> Result<(), Error> {
+    let (full_repo_path, worktree) = open_repository(settings);
 
     // "continue run" or "clean run"
     match &settings.no_clean {
@@ -109,17 +199,414 @@ pub fn run(settings: &RepocheckSettings) {
     }
 
     println!("Walking specified commit range...");
-    if let Err(e) = walk_commits(&repo, &full_repo_path, &settings) {
-        error_and_exit("Could not walk through specified commit range", &e);
+    walk_commits(&worktree.repo, &full_repo_path, settings, config)
+}
+
+/// Binary searches the commit range for the first commit which made a benchmark slower than
+/// at the first commit. Results of already benchmarked commits are reused.
+pub fn bisect(settings: &RepocheckSettings, bisect_settings: &BisectSettings) {
+    let (full_repo_path, worktree) = open_repository(settings);
+    let bisect_result = bisect_commits(&worktree.repo, &full_repo_path, settings, bisect_settings);
+    drop(worktree);
+    if let Err(e) = bisect_result {
+        error_and_exit("Could not bisect specified commit range", &e);
     }
-    println!("{}\n", "Successful!".green());
 }
 
-fn walk_commits(
+fn bisect_commits(
     repo: &Repository,
     full_repo_path: &Path,
     settings: &RepocheckSettings,
+    bisect_settings: &BisectSettings,
 ) -> Result<(), Error> {
+    let commits = commit_range(repo, settings)?;
+    if commits.len() < 2 {
+        println!("The commit range contains less than two commits, nothing to bisect.");
+        return Ok(());
+    }
+
+    let results_of = |index: usize| commit_results(repo, full_repo_path, settings, commits[index]);
+
+    println!(
+        "Bisecting {} commits for benchmarks matching '{}' getting slower by more than {}%...\n",
+        commits.len(),
+        bisect_settings.benchmark_regex,
+        bisect_settings.threshold_percent
+    );
+
+    // like 'git bisect skip', commits which don't build are stepped over
+    let mut baseline_index = 0;
+    let baseline = loop {
+        if let Some(results) = results_of(baseline_index)? {
+            break results;
+        }
+        baseline_index += 1;
+        if baseline_index == commits.len() - 1 {
+            return Err(Error::from_str(
+                "the builds of all but the last commit of the range failed, no baseline to compare with",
+            ));
+        }
+        println!("Using the next commit as baseline.\n");
+    };
+    let has_matching_benchmark = baseline
+        .iter()
+        .flat_map(|result| &result.benchmarks)
+        .any(|benchmark| bisect_settings.benchmark_regex.is_match(&benchmark.name));
+    if !has_matching_benchmark {
+        return Err(Error::from_str(&format!(
+            "no benchmark of the baseline commit matches '{}'",
+            bisect_settings.benchmark_regex
+        )));
+    }
+
+    let outcome = bisect_search(baseline_index, commits.len() - 1, |index| {
+        Ok(results_of(index)?.map(|results| regressions(&baseline, &results, bisect_settings)))
+    })?;
+    let (good, bad, bad_regressions) = match outcome {
+        BisectOutcome::NoRegression => {
+            println!(
+                "{}",
+                "No regression found between the first and the last commit of the range.".green()
+            );
+            return Ok(());
+        }
+        BisectOutcome::FirstBad {
+            good,
+            bad,
+            regressions,
+        } => {
+            let first_bad_commit = repo.find_commit(commits[bad])?;
+            println!(
+                "\n{}",
+                format!("First bad commit: {}", first_bad_commit.id()).red()
+            );
+            println!(
+                "Author: {} <{}>",
+                first_bad_commit.author().name().unwrap_or(""),
+                first_bad_commit.author().email().unwrap_or("")
+            );
+            println!("\n    {}\n", first_bad_commit.summary().unwrap_or(""));
+            (good, bad, regressions)
+        }
+        BisectOutcome::Range {
+            good,
+            bad,
+            regressions,
+        } => {
+            println!(
+                "\n{}",
+                "The first bad commit could not be isolated, the builds in between failed. It is one of:"
+                    .red()
+            );
+            for &oid in &commits[good + 1..=bad] {
+                let commit = repo.find_commit(oid)?;
+                println!("{} {}", commit.id(), commit.summary().unwrap_or(""));
+            }
+            println!();
+            (good, bad, regressions)
+        }
+    };
+    for comparison in bad_regressions {
+        println!(
+            "{} / {}: {}",
+            comparison.exe_name,
+            comparison.benchmark_name,
+            format_delta(comparison.delta_percent)
+        );
+    }
+
+    if let Some(repetitions) = bisect_settings.confirm_repetitions {
+        confirm_regression(
+            repo,
+            settings,
+            bisect_settings,
+            commits[good],
+            commits[bad],
+            repetitions,
+        )?;
+    }
+    Ok(())
+}
+
+/// Result of a bisection, indices into the bisected commit range.
+#[derive(Debug, PartialEq)]
+enum BisectOutcome<T> {
+    NoRegression,
+    /// `good` is the last commit without and `bad` the first commit with the regressions
+    FirstBad {
+        good: usize,
+        bad: usize,
+        regressions: Vec<T>,
+    },
+    /// The first bad commit is one of the commits after `good` up to `bad`, all commits in
+    /// between were skipped
+    Range {
+        good: usize,
+        bad: usize,
+        regressions: Vec<T>,
+    },
+}
+
+/// Binary searches the commits after `good` up to `last` for the first one `test` finds
+/// regressions for. `test` returns `None` for a commit which can't be tested, e.g. because its
+/// build failed, such commits are skipped and the nearest untested commit is tried instead.
+fn bisect_search<T, F>(good: usize, last: usize, mut test: F) -> Result<BisectOutcome<T>, Error>
+where
+    F: FnMut(usize) -> Result<Option<Vec<T>>, Error>,
+{
+    let mut good = good;
+    let mut skipped = Vec::new();
+
+    // the last testable commit of the range has to be regressed
+    let mut bad = last;
+    let mut bad_regressions = loop {
+        if bad == good {
+            return Ok(BisectOutcome::NoRegression);
+        }
+        match test(bad)? {
+            Some(regressions) if regressions.is_empty() => return Ok(BisectOutcome::NoRegression),
+            Some(regressions) => break regressions,
+            None => {
+                skipped.push(bad);
+                bad -= 1;
+            }
+        }
+    };
+
+    // invariant: the commit at `good` is not regressed, the one at `bad` is
+    while bad - good > 1 {
+        let untested: Vec<usize> = (good + 1..bad)
+            .filter(|index| !skipped.contains(index))
+            .collect();
+        if untested.is_empty() {
+            return Ok(BisectOutcome::Range {
+                good,
+                bad,
+                regressions: bad_regressions,
+            });
+        }
+        println!(
+            "{}",
+            format!("Bisecting: {} commits left to test", untested.len()).cyan()
+        );
+        let middle = good + (bad - good) / 2;
+        let middle = *untested
+            .iter()
+            .min_by_key(|&&index| (index as isize - middle as isize).abs())
+            .unwrap();
+        match test(middle)? {
+            Some(regressions) if regressions.is_empty() => good = middle,
+            Some(regressions) => {
+                bad = middle;
+                bad_regressions = regressions;
+            }
+            None => skipped.push(middle),
+        }
+    }
+
+    Ok(BisectOutcome::FirstBad {
+        good,
+        bad,
+        regressions: bad_regressions,
+    })
+}
+
+/// Benchmarks the last good and the first bad commit again with several repetitions and
+/// compares the medians, so a single noisy measurement can't cause a wrong bisect result.
+fn confirm_regression(
+    repo: &Repository,
+    settings: &RepocheckSettings,
+    bisect_settings: &BisectSettings,
+    good_commit: Oid,
+    bad_commit: Oid,
+    repetitions: u32,
+) -> Result<(), Error> {
+    println!(
+        "\nConfirming the regression with {} repetitions...",
+        repetitions
+    );
+    let benchmark_args = [
+        format!("--benchmark_repetitions={}", repetitions),
+        "--benchmark_report_aggregates_only=true".to_string(),
+    ];
+    let benchmark_with_repetitions = |oid: Oid| {
+        let commit = repo.find_commit(oid)?;
+        match build_and_benchmark(repo, settings, &commit, &benchmark_args)? {
+            CommitRun::Benchmarked(results) => Ok(median_aggregates(results)),
+            CommitRun::BuildFailed(failure) => Err(Error::from_str(&format!(
+                "build of commit {} failed: '{}' exited with {}",
+                id_to_str(oid.as_bytes()),
+                failure.command,
+                failure.status
+            ))),
+        }
+    };
+    let good_results = benchmark_with_repetitions(good_commit)?;
+    let bad_results = benchmark_with_repetitions(bad_commit)?;
+
+    if regressions(&good_results, &bad_results, bisect_settings).is_empty() {
+        println!(
+            "{}",
+            "Regression not confirmed, the medians of the repetitions are within the threshold."
+                .yellow()
+        );
+    } else {
+        println!("{}", "Regression confirmed.".red());
+    }
+    Ok(())
+}
+
+/// Returns the benchmarks matching the bisect regex which got slower than the threshold.
+fn regressions(
+    baseline: &[BenchmarkResults],
+    current: &[BenchmarkResults],
+    bisect_settings: &BisectSettings,
+) -> Vec<BenchmarkComparison> {
+    compare_results(baseline, current, bisect_settings.threshold_percent)
+        .into_iter()
+        .filter(|comparison| {
+            comparison.status == ComparisonStatus::Slower
+                && bisect_settings
+                    .benchmark_regex
+                    .is_match(&comparison.benchmark_name)
+        })
+        .collect()
+}
+
+/// Keeps only the median of repeated benchmarks, named like the single benchmark.
+fn median_aggregates(mut results: Vec<BenchmarkResults>) -> Vec<BenchmarkResults> {
+    for result in &mut results {
+        result
+            .benchmarks
+            .retain(|benchmark| benchmark.name.ends_with("_median"));
+        for benchmark in &mut result.benchmarks {
+            benchmark
+                .name
+                .truncate(benchmark.name.len() - "_median".len());
+        }
+    }
+    results
+}
+
+/// Opens the configured repository and adds a temporary worktree to it.
+fn open_repository(settings: &RepocheckSettings) -> (PathBuf, RepocheckWorktree) {
+    let full_repo_path = match std::fs::canonicalize(settings.repo_path.as_path()) {
+        Ok(path) => path,
+        Err(e) => {
+            error_and_exit("Invalid path to repository", &e);
+        }
+    };
+
+    let repo = match Repository::open(full_repo_path.as_path()) {
+        Ok(repo) => repo,
+        Err(e) => {
+            error_and_exit("Could not open repository", &e);
+        }
+    };
+
+    handle_interrupts();
+    let keep = settings.keep_worktree.unwrap_or(false);
+    let worktree = match RepocheckWorktree::create(repo, keep) {
+        Ok(worktree) => worktree,
+        Err(e) => error_and_exit("Could not create temporary worktree", &e),
+    };
+    println!(
+        "Using temporary worktree '{}' of repository '{}'...",
+        worktree.path().to_string_lossy(),
+        full_repo_path.to_string_lossy()
+    );
+
+    (full_repo_path, worktree)
+}
+
+/// Temporary worktree the commits are checked out and built in, so the checkout of the
+/// configured repository is left untouched. It has its own branch named like the worktree.
+/// Worktree and branch are removed when it is dropped, also if repocheck fails or panics.
+struct RepocheckWorktree {
+    main_repo: Repository,
+    worktree: Worktree,
+    repo: Repository,
+    keep: bool,
+}
+
+impl RepocheckWorktree {
+    fn create(main_repo: Repository, keep: bool) -> Result<RepocheckWorktree, Error> {
+        let name = format!("beast-repocheck-{}", &Uuid::new_v4().to_string()[..8]);
+        let path = std::env::temp_dir().join(&name);
+        let worktree = main_repo.worktree(&name, &path, None)?;
+        let repo = Repository::open_from_worktree(&worktree)?;
+        Ok(RepocheckWorktree {
+            main_repo,
+            worktree,
+            repo,
+            keep,
+        })
+    }
+
+    fn path(&self) -> &Path {
+        self.worktree.path()
+    }
+}
+
+impl Drop for RepocheckWorktree {
+    /// Removes the worktree including its build results and its branch, unless it is kept.
+    fn drop(&mut self) {
+        let name = self.worktree.name().unwrap().to_string();
+        if self.keep {
+            let path = self.path().to_string_lossy();
+            println!(
+                "Keeping worktree '{}', remove it with 'git worktree remove --force {}' and 'git branch -D {}'.",
+                path, path, name
+            );
+            return;
+        }
+
+        let removal = self
+            .worktree
+            .prune(Some(
+                WorktreePruneOptions::new().valid(true).working_tree(true),
+            ))
+            .and_then(|_| self.main_repo.find_branch(&name, BranchType::Local))
+            .and_then(|mut branch| branch.delete());
+        if let Err(e) = removal {
+            warn(
+                &format!("Could not remove temporary worktree '{}'", name),
+                &e,
+            );
+        }
+    }
+}
+
+#[cfg(unix)]
+fn handle_interrupts() {
+    extern "C" fn on_signal(_: libc::c_int) {
+        INTERRUPTED.store(true, Ordering::SeqCst);
+    }
+    // the running build command receives Ctrl-C as well, so it stops right away. The
+    // default handler is restored by the first signal, so a second Ctrl-C exits at once.
+    unsafe {
+        let mut action: libc::sigaction = std::mem::zeroed();
+        action.sa_sigaction = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+        action.sa_flags = libc::SA_RESETHAND;
+        libc::sigemptyset(&mut action.sa_mask);
+        libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+        libc::sigaction(libc::SIGTERM, &action, std::ptr::null_mut());
+    }
+}
+
+#[cfg(not(unix))]
+fn handle_interrupts() {}
+
+fn check_interrupted() -> Result<(), Error> {
+    if INTERRUPTED.load(Ordering::SeqCst) {
+        Err(Error::from_str("Interrupted"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the first parent history from `from_commit` to `to_commit`, oldest first.
+fn commit_range(repo: &Repository, settings: &RepocheckSettings) -> Result<Vec<Oid>, Error> {
     let from_commit_oid = Oid::from_str(settings.from_commit.as_str())?;
     let to_commit_oid = Oid::from_str(settings.to_commit.as_str())?;
     let from_commit_parent = repo.find_commit(from_commit_oid)?.parent(0)?;
@@ -128,20 +615,65 @@ fn walk_commits(
     revwalk.push(to_commit_oid)?;
     revwalk.hide(from_commit_parent.id())?;
     revwalk.simplify_first_parent()?;
-    revwalk.set_sorting(git2::Sort::REVERSE | git2::Sort::TIME)?;
+    revwalk.set_sorting(git2::Sort::REVERSE | git2::Sort::TOPOLOGICAL | git2::Sort::TIME)?;
+    revwalk.collect()
+}
 
-    for rev in revwalk {
-        let commit = repo.find_commit(rev?)?;
+/// Returns the stored results of a commit, the commit is benchmarked if there are none.
+/// Returns `None` if the build of the commit failed, now or in a previous run.
+fn commit_results(
+    repo: &Repository,
+    full_repo_path: &Path,
+    settings: &RepocheckSettings,
+    oid: Oid,
+) -> Result<Option<Vec<BenchmarkResults>>, Error> {
+    let commit_id_str = id_to_str(oid.as_bytes());
+    let export_file_path = commit_results_path(full_repo_path, settings, &commit_id_str);
+    let build_log_path = build_log_path(full_repo_path, settings, &commit_id_str);
+    if export_file_path.exists() {
+        println!("Using stored results for commit {}.", commit_id_str);
+        return Ok(Some(parse_results_file(export_file_path)));
+    }
+    if build_log_path.exists() {
+        println!(
+            "Skipping commit {}, its build failed before (see {}).",
+            commit_id_str,
+            build_log_path.to_string_lossy()
+        );
+        return Ok(None);
+    }
+
+    std::fs::create_dir_all(export_file_path.parent().unwrap()).unwrap();
+    match build_and_benchmark(repo, settings, &repo.find_commit(oid)?, &[])? {
+        CommitRun::Benchmarked(results) => {
+            export_results(&results, &export_file_path)?;
+            Ok(Some(results))
+        }
+        CommitRun::BuildFailed(failure) => {
+            record_build_failure(&failure, &commit_id_str, &build_log_path);
+            println!("Skipping commit {}.\n", commit_id_str);
+            Ok(None)
+        }
+    }
+}
+
+fn walk_commits(
+    repo: &Repository,
+    full_repo_path: &Path,
+    settings: &RepocheckSettings,
+    config: &AppConfig,
+) -> Result<(), Error> {
+    for oid in commit_range(repo, settings)? {
+        check_interrupted()?;
+        let commit = repo.find_commit(oid)?;
 
         // create filename to save results
         let commit_id_str = id_to_str(commit.id().as_bytes());
-        let export_file_name = "commit_".to_string() + commit_id_str.as_str() + ".json";
-        let export_dir = export_dir(full_repo_path, &settings.branch_name);
-        let mut export_file_path = export_dir;
-        export_file_path.push(Path::new(&export_file_name));
+        let export_file_path = commit_results_path(full_repo_path, settings, &commit_id_str);
+        let build_log_path = build_log_path(full_repo_path, settings, &commit_id_str);
 
         if let Some(true) = settings.no_clean.as_ref() {
-            if export_file_path.exists() {
+            if export_file_path.exists() || build_log_path.exists() {
                 println!(
                     "Results for commit {} already present. Continue...",
                     commit_id_str
@@ -150,65 +682,192 @@ fn walk_commits(
             }
         }
 
-        checkout_commit(repo, &commit)?;
+        // create parent dir
+        let export_parent_dir = export_file_path.parent().unwrap();
+        std::fs::create_dir_all(export_parent_dir).unwrap();
 
-        println!("Building for commit {}...", &commit.id());
+        let results = match build_and_benchmark(repo, settings, &commit, &[])? {
+            CommitRun::Benchmarked(results) => results,
+            CommitRun::BuildFailed(failure) => {
+                record_build_failure(&failure, &commit_id_str, &build_log_path);
+                match settings.on_build_failure {
+                    Some(BuildFailureAction::Abort) => {
+                        return Err(build_failed_error(&commit_id_str, &build_log_path))
+                    }
+                    Some(BuildFailureAction::Skip) | None => {
+                        println!("Skipping commit {}.\n", commit_id_str);
+                        continue;
+                    }
+                }
+            }
+        };
 
-        let repo_workdir = repo.workdir().unwrap();
-        println!(
-            "Using working directory {}...",
-            repo_workdir.to_string_lossy()
-        );
+        export_results(&results, &export_file_path)?;
 
-        for cmd in settings.build_commands.lines() {
-            println!("Executing cmd: {}", cmd.blue());
-            let mut build_cmd = shell(cmd);
-            let output = match build_cmd.current_dir(repo_workdir).execute_output() {
-                Ok(res) => res,
-                Err(e) => error_and_exit("Command execution error", &e),
-            };
-            let output_str = String::from_utf8(output.stdout).unwrap();
-            print!("{}\n", output_str.as_str());
+        if let Some(true) = settings.push {
+            // connect for every commit, an unreachable database only spools this commit
+            let entries = entries_from_results(results, &[], &BTreeMap::new());
+            let push_result = try_push_or_spool(config, &entries, DuplicatePolicy::Skip)
+                .map_err(|e| Error::from_str(&format!("Could not push results: {}", e)))?;
+            if let Some(stored_count) = push_result {
+                println!(
+                    "Pushed {} results for commit {}.",
+                    stored_count, commit_id_str
+                );
+            }
         }
+    }
 
-        println!("{}\n", "Successful!".green());
+    Ok(())
+}
 
-        let benchmark_paths = find_executables(repo_workdir, &settings.benchmark_regex);
-        let mut results = execute_benchmarks(benchmark_paths);
+/// Checks out the commit, runs the build commands and executes the found benchmarks.
+/// Benchmarks are only executed if all build commands succeeded.
+fn build_and_benchmark(
+    repo: &Repository,
+    settings: &RepocheckSettings,
+    commit: &Commit,
+    benchmark_args: &[String],
+) -> Result<CommitRun, Error> {
+    checkout_commit(repo, commit)?;
 
-        append_commit_id(&mut results, &commit_id_str);
+    println!("Building for commit {}...", &commit.id());
 
-        // create parent dir
-        let export_parent_dir = export_file_path.parent().unwrap();
-        std::fs::create_dir_all(export_parent_dir).unwrap();
+    let repo_workdir = repo.workdir().unwrap();
+    println!(
+        "Using working directory {}...",
+        repo_workdir.to_string_lossy()
+    );
+
+    let mut log = String::new();
+    for cmd in settings.build_commands.lines() {
+        println!("Executing cmd: {}", cmd.blue());
+        let mut build_cmd = shell(cmd);
+        build_cmd.stdout(Stdio::piped());
+        build_cmd.stderr(Stdio::piped());
+        let output = match build_cmd.current_dir(repo_workdir).execute_output() {
+            Ok(res) => res,
+            Err(e) => {
+                return Err(Error::from_str(&format!(
+                    "Command execution error of '{}': {}",
+                    cmd, e
+                )))
+            }
+        };
+        check_interrupted()?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        println!("{}", stdout);
+        eprint!("{}", stderr);
+        writeln!(&mut log, "$ {}\n{}{}", cmd, stdout, stderr).unwrap();
 
-        export_results_to_file(&results, export_file_path.as_path());
+        if !output.status.success() {
+            println!(
+                "{}\n",
+                format!("Build failed: '{}' exited with {}", cmd, output.status).red()
+            );
+            return Ok(CommitRun::BuildFailed(BuildFailure {
+                command: cmd.to_string(),
+                status: output.status,
+                log,
+            }));
+        }
     }
 
-    Ok(())
+    println!("{}\n", "Successful!".green());
+
+    let benchmark_paths = find_executables(repo_workdir, &settings.benchmark_regex);
+    let mut results = execute_benchmarks(benchmark_paths, benchmark_args);
+
+    append_commit_id(&mut results, &id_to_str(commit.id().as_bytes()));
+    Ok(CommitRun::Benchmarked(results))
 }
 
-fn checkout_branch(repo: &Repository, branch_name: &str) -> Result<(), Error> {
-    let obj = repo.revparse_single(&("refs/heads/".to_owned() + branch_name))?;
-    repo.checkout_tree(&obj, None)?;
-    repo.set_head(&("refs/heads/".to_owned() + branch_name))?;
-    Ok(())
+fn export_results(results: &Vec<BenchmarkResults>, export_file_path: &Path) -> Result<(), Error> {
+    export_results_to_file(results, export_file_path).map_err(|e| {
+        Error::from_str(&format!(
+            "Could not write to file {}: {}",
+            export_file_path.to_string_lossy(),
+            e
+        ))
+    })
+}
+
+/// Keeps the build log of a failed commit next to the results of the other commits.
+fn record_build_failure(failure: &BuildFailure, commit_id_str: &str, build_log_path: &Path) {
+    let record = format!(
+        "build failed for commit {}: '{}' exited with {}\n\n{}",
+        commit_id_str, failure.command, failure.status, failure.log
+    );
+    fs::write(build_log_path, record).unwrap_or_else(|_| {
+        panic!(
+            "Could not write build log {}!",
+            build_log_path.to_string_lossy()
+        )
+    });
+    println!("Build log saved to {}.", build_log_path.to_string_lossy());
+}
+
+fn build_failed_error(commit_id_str: &str, build_log_path: &Path) -> Error {
+    Error::from_str(&format!(
+        "build failed for commit {}, see {}",
+        commit_id_str,
+        build_log_path.to_string_lossy()
+    ))
 }
 
 fn checkout_commit(repo: &Repository, commit: &Commit) -> Result<(), Error> {
     repo.checkout_tree(commit.as_object(), None)?;
     repo.set_head_detached(commit.id())?;
+    update_submodules(repo)
+}
+
+/// Initializes and checks out the submodules recorded by the checked out commit, like
+/// `git submodule update --init --recursive`.
+fn update_submodules(repo: &Repository) -> Result<(), Error> {
+    for mut submodule in repo.submodules()? {
+        println!(
+            "Updating submodule {}...",
+            submodule.path().to_string_lossy()
+        );
+        submodule.update(true, None)?;
+        update_submodules(&submodule.open()?)?;
+    }
     Ok(())
 }
 
 fn export_dir(repo_path: &Path, branch_name: &str) -> PathBuf {
     let mut export_file_path = preferences::prefs_base_dir().unwrap();
     export_file_path.push(Path::new("beastrepocheck"));
-    export_file_path.push(repo_path.components().last().unwrap());
+    export_file_path.push(repo_path.components().next_back().unwrap());
     export_file_path.push(Path::new(branch_name));
     export_file_path
 }
 
+fn commit_results_path(
+    full_repo_path: &Path,
+    settings: &RepocheckSettings,
+    commit_id_str: &str,
+) -> PathBuf {
+    let mut export_file_path = export_dir(full_repo_path, &settings.branch_name);
+    export_file_path.push(Path::new(&format!("commit_{}.json", commit_id_str)));
+    export_file_path
+}
+
+fn build_log_path(
+    full_repo_path: &Path,
+    settings: &RepocheckSettings,
+    commit_id_str: &str,
+) -> PathBuf {
+    let mut build_log_path = export_dir(full_repo_path, &settings.branch_name);
+    build_log_path.push(Path::new(&format!(
+        "commit_{}{}",
+        commit_id_str, BUILD_LOG_SUFFIX
+    )));
+    build_log_path
+}
+
 fn append_commit_id(results: &mut Vec<BenchmarkResults>, commit_id_str: &str) {
     for result in results {
         result.commit = Some(commit_id_str.to_string());
@@ -226,6 +885,178 @@ fn id_to_str(oid: &[u8]) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::settings::ValueSource;
+    use crate::storage::tests::test_entry;
+
+    /// Fake google benchmark executable writing a single result.
+    #[cfg(unix)]
+    const BENCHMARK_SCRIPT: &str = r#"#!/bin/sh
+for arg in "$@"; do
+    case $arg in --benchmark_out=*) OUT=${arg#--benchmark_out=};; esac
+done
+cat > "$OUT" <<JSON
+{"context":{"date":"2021-01-01T00:00:00+00:00","executable":"$0","num_cpus":1,"mhz_per_cpu":1},
+ "benchmarks":[{"name":"BM_Foo","iterations":1,"real_time":1.0,"cpu_time":1.0,"time_unit":"ns"}]}
+JSON
+"#;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_worktree_removed_after_push_error() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir =
+            std::env::temp_dir().join(format!("beast_repocheck_push_test_{}", std::process::id()));
+        let repo = Repository::init(&dir).unwrap();
+        let script_path = dir.join("fake_benchmark");
+        fs::write(&script_path, BENCHMARK_SCRIPT).unwrap();
+        fs::set_permissions(&script_path, fs::Permissions::from_mode(0o755)).unwrap();
+
+        // commit_range starts at the parent of from_commit, so two commits are needed
+        let signature = git2::Signature::now("Tester", "tester@example.com").unwrap();
+        let mut index = repo.index().unwrap();
+        index.add_path(Path::new("fake_benchmark")).unwrap();
+        let tree = repo.find_tree(index.write_tree().unwrap()).unwrap();
+        let first = repo
+            .commit(Some("HEAD"), &signature, &signature, "first", &tree, &[])
+            .unwrap();
+        let first = repo.find_commit(first).unwrap();
+        let second = repo
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "second",
+                &tree,
+                &[&first],
+            )
+            .unwrap();
+
+        let settings = RepocheckSettings {
+            version: 1,
+            repo_path: dir.clone(),
+            branch_name: "master".to_string(),
+            from_commit: second.to_string(),
+            to_commit: second.to_string(),
+            build_commands: "true".to_string(),
+            benchmark_regex: "^fake_benchmark$".to_string(),
+            no_clean: Some(false),
+            push: Some(true),
+            keep_worktree: None,
+            on_build_failure: None,
+        };
+        // a sqlite database in a missing directory can't be opened and isn't spooled
+        let mut config = AppConfig::init(DEFAULT_PROFILE, true);
+        config.override_value(DATABASE_BACKEND_KEY, "sqlite", ValueSource::CommandLine);
+        config.override_value(
+            DATABASE_SQLITE_PATH_KEY,
+            &dir.join("missing").join("results.sqlite").to_string_lossy(),
+            ValueSource::CommandLine,
+        );
+
+        let result = run_in_worktree(&settings, &config);
+        assert!(result
+            .unwrap_err()
+            .message()
+            .contains("Could not push results"));
+        assert!(repo.worktrees().unwrap().is_empty());
+        let branches: Vec<String> = repo
+            .branches(Some(BranchType::Local))
+            .unwrap()
+            .map(|branch| branch.unwrap().0.name().unwrap().unwrap().to_string())
+            .collect();
+        assert!(branches
+            .iter()
+            .all(|name| !name.starts_with("beast-repocheck")));
+
+        let _ = fs::remove_dir_all(export_dir(&dir, &settings.branch_name));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_regressions_of_matching_benchmarks() {
+        let bisect_settings = BisectSettings {
+            benchmark_regex: Regex::new("^BM_Foo").unwrap(),
+            threshold_percent: 10.0,
+            confirm_repetitions: None,
+        };
+        let baseline = vec![test_entry("simple_benchmark", "").results];
+        let mut current = baseline.clone();
+        for benchmark in &mut current[0].benchmarks {
+            benchmark.cpu_time *= 2.0;
+        }
+        let found = regressions(&baseline, &current, &bisect_settings);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].benchmark_name, "BM_Foo");
+
+        // the baseline cpu time is 1.0, increases below the threshold are no regression
+        current[0].benchmarks[0].cpu_time = 1.08;
+        assert!(regressions(&baseline, &current, &bisect_settings).is_empty());
+        current[0].benchmarks[0].cpu_time = 1.15;
+        assert_eq!(regressions(&baseline, &current, &bisect_settings).len(), 1);
+    }
+
+    #[test]
+    fn test_bisect_search_skips_failed_builds() {
+        // commits from index 5 on are regressed, `failed` commits don't build
+        let search = |failed: &[usize]| {
+            let mut tested = Vec::new();
+            let outcome = bisect_search(0, 9, |index| {
+                tested.push(index);
+                if failed.contains(&index) {
+                    return Ok(None);
+                }
+                Ok(Some(if index >= 5 { vec![index] } else { vec![] }))
+            })
+            .unwrap();
+            (outcome, tested)
+        };
+
+        let (outcome, _) = search(&[]);
+        assert_eq!(
+            outcome,
+            BisectOutcome::FirstBad {
+                good: 4,
+                bad: 5,
+                regressions: vec![5]
+            }
+        );
+
+        let (outcome, tested) = search(&[9, 4]);
+        assert_eq!(
+            outcome,
+            BisectOutcome::Range {
+                good: 3,
+                bad: 5,
+                regressions: vec![5]
+            }
+        );
+        assert_eq!(tested.iter().filter(|&&index| index == 4).count(), 1);
+
+        let (outcome, _) = search(&[3, 6]);
+        assert_eq!(
+            outcome,
+            BisectOutcome::FirstBad {
+                good: 4,
+                bad: 5,
+                regressions: vec![5]
+            }
+        );
+
+        let (outcome, _) = search(&[4, 5]);
+        assert_eq!(
+            outcome,
+            BisectOutcome::Range {
+                good: 3,
+                bad: 6,
+                regressions: vec![6]
+            }
+        );
+
+        let (outcome, _) = search(&[5, 6, 7, 8, 9]);
+        assert_eq!(outcome, BisectOutcome::NoRegression);
+    }
+
     #[test]
     fn test_parse() {
         let test_yaml_path =
@@ -235,4 +1066,19 @@ mod tests {
         assert_eq!(parsed_settings.repo_path.as_path(), Path::new("."));
         assert_eq!(parsed_settings.branch_name, "master");
     }
+
+    #[test]
+    fn test_parse_build_failure_action() {
+        let yaml = "version: 1\nrepo_path: .\nbranch_name: master\nfrom_commit: a\nto_commit: b\n\
+                    build_commands: make\nbenchmark_regex: bench\non_build_failure: abort\n";
+        let parsed_settings: RepocheckSettings = serde_yaml::from_str(yaml).unwrap();
+        assert_eq!(
+            parsed_settings.on_build_failure,
+            Some(BuildFailureAction::Abort)
+        );
+        assert_eq!(
+            BuildFailureAction::from_name("skip"),
+            Some(BuildFailureAction::Skip)
+        );
+    }
 }
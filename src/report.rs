@@ -0,0 +1,382 @@
+use crate::parse::*;
+use crate::resample::*;
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq)]
+pub enum ComparisonStatus {
+    Faster,
+    Slower,
+    Unchanged,
+    New,
+    Removed,
+}
+
+#[derive(Debug)]
+pub struct BenchmarkComparison {
+    pub exe_name: String,
+    pub benchmark_name: String,
+    pub baseline_ns: Option<f64>,
+    pub current_ns: Option<f64>,
+    /// Infinite if the baseline time is zero.
+    pub delta_percent: Option<f64>,
+    pub status: ComparisonStatus,
+    /// Whether the change exceeds the threshold and the measured noise, see `is_significant`.
+    pub significant: bool,
+}
+
+impl ComparisonStatus {
+    fn as_str(&self) -> &str {
+        match self {
+            ComparisonStatus::Faster => "faster",
+            ComparisonStatus::Slower => "slower",
+            ComparisonStatus::Unchanged => "unchanged",
+            ComparisonStatus::New => "new",
+            ComparisonStatus::Removed => "removed",
+        }
+    }
+}
+
+/// Compares the cpu times of all benchmarks found in baseline and current results.
+/// Deltas beyond +/- `threshold_percent` are reported as faster/slower.
+pub fn compare_results(
+    baseline: &[BenchmarkResults],
+    current: &[BenchmarkResults],
+    threshold_percent: f64,
+) -> Vec<BenchmarkComparison> {
+    let baseline_times = cpu_times_by_benchmark(baseline);
+    let current_times = cpu_times_by_benchmark(current);
+    let baseline_stddevs = stddevs_by_benchmark(&baseline_times);
+    let current_stddevs = stddevs_by_benchmark(&current_times);
+
+    let mut comparisons = vec![];
+
+    for (key, current_ns) in &current_times {
+        let baseline_ns = baseline_times.get(key).copied();
+        let (delta_percent, status) = match baseline_ns {
+            Some(baseline_ns) => {
+                let delta = relative_delta_percent(baseline_ns, *current_ns);
+                let status = if delta > threshold_percent {
+                    ComparisonStatus::Slower
+                } else if delta < -threshold_percent {
+                    ComparisonStatus::Faster
+                } else {
+                    ComparisonStatus::Unchanged
+                };
+                (Some(delta), status)
+            }
+            None => (None, ComparisonStatus::New),
+        };
+        let significant = match baseline_ns {
+            Some(baseline_ns) if status != ComparisonStatus::Unchanged => is_significant(
+                baseline_ns,
+                *current_ns,
+                baseline_stddevs.get(key).copied(),
+                current_stddevs.get(key).copied(),
+            ),
+            _ => false,
+        };
+        comparisons.push(BenchmarkComparison {
+            exe_name: key.0.clone(),
+            benchmark_name: key.1.clone(),
+            baseline_ns,
+            current_ns: Some(*current_ns),
+            delta_percent,
+            status,
+            significant,
+        });
+    }
+
+    for (key, baseline_ns) in &baseline_times {
+        if !current_times.contains_key(key) {
+            comparisons.push(BenchmarkComparison {
+                exe_name: key.0.clone(),
+                benchmark_name: key.1.clone(),
+                baseline_ns: Some(*baseline_ns),
+                current_ns: None,
+                delta_percent: None,
+                status: ComparisonStatus::Removed,
+                significant: false,
+            });
+        }
+    }
+
+    comparisons
+        .sort_by(|a, b| (&a.exe_name, &a.benchmark_name).cmp(&(&b.exe_name, &b.benchmark_name)));
+    comparisons
+}
+
+/// Creates a markdown summary suitable for pull request comments.
+pub fn markdown_report(
+    comparisons: &[BenchmarkComparison],
+    current: &[BenchmarkResults],
+    time_unit: &str,
+    threshold_percent: f64,
+) -> String {
+    let (changed, unchanged): (Vec<&BenchmarkComparison>, Vec<&BenchmarkComparison>) = comparisons
+        .iter()
+        .partition(|c| c.status != ComparisonStatus::Unchanged);
+
+    let mut report = String::new();
+    writeln!(report, "## Benchmark comparison\n").unwrap();
+    writeln!(
+        report,
+        "{} changed, {} unchanged (threshold: +/-{}%)\n",
+        changed.len(),
+        unchanged.len(),
+        threshold_percent
+    )
+    .unwrap();
+
+    if !changed.is_empty() {
+        write_markdown_table(&mut report, &changed, time_unit);
+    }
+
+    if !unchanged.is_empty() {
+        writeln!(
+            report,
+            "\n<details>\n<summary>Unchanged benchmarks ({})</summary>\n",
+            unchanged.len()
+        )
+        .unwrap();
+        write_markdown_table(&mut report, &unchanged, time_unit);
+        writeln!(report, "\n</details>").unwrap();
+    }
+
+    if let Some(first_result) = current.first() {
+        let context = &first_result.context;
+        writeln!(report, "\n<details>\n<summary>Environment</summary>\n").unwrap();
+        writeln!(report, "| Property | Value |\n|---|---|").unwrap();
+        writeln!(report, "| Date | {} |", context.date).unwrap();
+        if let Some(host_name) = &context.host_name {
+            writeln!(report, "| Host | {} |", host_name).unwrap();
+        }
+        writeln!(report, "| CPU count | {} |", context.num_cpus).unwrap();
+        writeln!(report, "| MHz/CPU | {} |", context.mhz_per_cpu).unwrap();
+        if let Some(build_type) = &context.library_build_type {
+            writeln!(report, "| Library build type | {} |", build_type).unwrap();
+        }
+        writeln!(report, "\n</details>").unwrap();
+    }
+
+    report
+}
+
+/// Creates a plain text summary for console output.
+pub fn text_report(comparisons: &[BenchmarkComparison], time_unit: &str) -> String {
+    let mut report = String::new();
+    for comparison in comparisons {
+        writeln!(
+            report,
+            "{:<60} {:>12} -> {:<12} {:>9} {}",
+            full_benchmark_name(comparison),
+            format_time(comparison.baseline_ns, time_unit),
+            format_time(comparison.current_ns, time_unit),
+            format_delta(comparison.delta_percent),
+            comparison.status.as_str()
+        )
+        .unwrap();
+    }
+    report
+}
+
+fn write_markdown_table(
+    report: &mut String,
+    comparisons: &[&BenchmarkComparison],
+    time_unit: &str,
+) {
+    writeln!(
+        report,
+        "| Benchmark | Baseline [{0}] | Current [{0}] | Delta | Sig. | Status |",
+        time_unit
+    )
+    .unwrap();
+    writeln!(report, "|---|---:|---:|---:|:---:|---|").unwrap();
+    for comparison in comparisons {
+        writeln!(
+            report,
+            "| `{}` | {} | {} | {} | {} | {} |",
+            full_benchmark_name(comparison),
+            format_time(comparison.baseline_ns, time_unit),
+            format_time(comparison.current_ns, time_unit),
+            format_delta(comparison.delta_percent),
+            if comparison.significant { "*" } else { "" },
+            comparison.status.as_str()
+        )
+        .unwrap();
+    }
+}
+
+fn cpu_times_by_benchmark(results: &[BenchmarkResults]) -> BTreeMap<(String, String), f64> {
+    let mut cpu_times = BTreeMap::new();
+    for result in results {
+        let exe_name = result
+            .context
+            .executable
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned();
+        for benchmark in &result.benchmarks {
+            cpu_times.insert(
+                (exe_name.clone(), benchmark.name.clone()),
+                to_nanos(benchmark.cpu_time, benchmark.time_unit.as_deref()),
+            );
+        }
+    }
+    cpu_times
+}
+
+/// Maps the benchmarks to the standard deviation reported by google benchmark for
+/// repeated runs (`BM_Foo_stddev`), for the `_mean` and `_median` aggregates as well.
+fn stddevs_by_benchmark(
+    cpu_times: &BTreeMap<(String, String), f64>,
+) -> BTreeMap<(String, String), f64> {
+    let mut stddevs = BTreeMap::new();
+    for ((exe_name, benchmark_name), stddev) in cpu_times {
+        if let Some(base_name) = benchmark_name.strip_suffix("_stddev") {
+            for suffix in &["", "_mean", "_median"] {
+                stddevs.insert((exe_name.clone(), base_name.to_string() + suffix), *stddev);
+            }
+        }
+    }
+    stddevs
+}
+
+/// A change beyond the threshold is significant unless it is within the noise of the
+/// measurements, i.e. the sum of the standard deviations of both runs if they are known.
+fn is_significant(
+    baseline: f64,
+    current: f64,
+    baseline_stddev: Option<f64>,
+    current_stddev: Option<f64>,
+) -> bool {
+    let noise = baseline_stddev.unwrap_or(0.0) + current_stddev.unwrap_or(0.0);
+    (current - baseline).abs() > noise
+}
+
+fn relative_delta_percent(baseline: f64, current: f64) -> f64 {
+    if baseline == current {
+        return 0.0;
+    }
+    // any change of a zero baseline is infinitely large
+    (current - baseline) / baseline * 100.0
+}
+
+fn full_benchmark_name(comparison: &BenchmarkComparison) -> String {
+    comparison.exe_name.clone() + "_" + comparison.benchmark_name.as_str()
+}
+
+fn format_time(time_ns: Option<f64>, time_unit: &str) -> String {
+    match time_ns {
+        Some(time_ns) => format!("{:.2}", from_nanos(time_ns, time_unit)),
+        None => "-".to_string(),
+    }
+}
+
+pub fn format_delta(delta_percent: Option<f64>) -> String {
+    match delta_percent {
+        Some(delta) if delta.is_infinite() => "n/a".to_string(),
+        Some(delta) => format!("{:+.1}%", delta),
+        None => "-".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn results_with_cpu_time(cpu_time: f64) -> BenchmarkResults {
+        BenchmarkResults {
+            commit: None,
+            run_id: None,
+            provenance: None,
+            context: BenchmarkContext {
+                date: "2021-01-01T00:00:00+00:00".to_string(),
+                executable: PathBuf::from("/tmp/simple_benchmark"),
+                num_cpus: 4,
+                mhz_per_cpu: 3000,
+                host_name: None,
+                library_build_type: None,
+            },
+            benchmarks: vec![BenchmarkResult {
+                name: "BM_Foo".to_string(),
+                iterations: 1,
+                real_time: cpu_time,
+                cpu_time,
+                time_unit: Some("ns".to_string()),
+            }],
+        }
+    }
+
+    #[test]
+    fn test_compare_results() {
+        let baseline = vec![results_with_cpu_time(1000.0)];
+
+        let slower = compare_results(&baseline, &[results_with_cpu_time(1200.0)], 5.0);
+        assert_eq!(slower.len(), 1);
+        assert_eq!(slower[0].status, ComparisonStatus::Slower);
+        assert_eq!(slower[0].delta_percent, Some(20.0));
+
+        let unchanged = compare_results(&baseline, &[results_with_cpu_time(1020.0)], 5.0);
+        assert_eq!(unchanged[0].status, ComparisonStatus::Unchanged);
+
+        let removed = compare_results(&baseline, &[], 5.0);
+        assert_eq!(removed[0].status, ComparisonStatus::Removed);
+
+        let sub_unit_baseline = vec![results_with_cpu_time(1.0)];
+        let sub_unit = compare_results(&sub_unit_baseline, &[results_with_cpu_time(1.1)], 5.0);
+        assert_eq!(sub_unit[0].status, ComparisonStatus::Slower);
+        assert!((sub_unit[0].delta_percent.unwrap() - 10.0).abs() < 1e-9);
+
+        let zero_baseline = vec![results_with_cpu_time(0.0)];
+        let from_zero = compare_results(&zero_baseline, &[results_with_cpu_time(5.0)], 5.0);
+        assert_eq!(from_zero[0].status, ComparisonStatus::Slower);
+        assert_eq!(format_delta(from_zero[0].delta_percent), "n/a");
+        let still_zero = compare_results(&zero_baseline, &[results_with_cpu_time(0.0)], 5.0);
+        assert_eq!(still_zero[0].status, ComparisonStatus::Unchanged);
+    }
+
+    #[test]
+    fn test_significance_marker() {
+        let with_stddev = |mean: f64, stddev: f64| {
+            let mut results = results_with_cpu_time(mean);
+            results.benchmarks[0].name = "BM_Foo_mean".to_string();
+            let mut stddev_result = results.benchmarks[0].clone();
+            stddev_result.name = "BM_Foo_stddev".to_string();
+            stddev_result.cpu_time = stddev;
+            results.benchmarks.push(stddev_result);
+            results
+        };
+        let baseline = vec![with_stddev(1000.0, 50.0)];
+
+        // beyond the threshold, but within the noise of both runs
+        let noisy = compare_results(&baseline, &[with_stddev(1080.0, 50.0)], 5.0);
+        let mean = noisy.iter().find(|c| c.benchmark_name == "BM_Foo_mean");
+        assert_eq!(mean.unwrap().status, ComparisonStatus::Slower);
+        assert!(!mean.unwrap().significant);
+
+        let slower = compare_results(&baseline, &[with_stddev(1200.0, 50.0)], 5.0);
+        let mean = slower.iter().find(|c| c.benchmark_name == "BM_Foo_mean");
+        assert!(mean.unwrap().significant);
+
+        // without repetitions every change beyond the threshold is significant
+        let single = compare_results(
+            &[results_with_cpu_time(1000.0)],
+            &[results_with_cpu_time(1200.0)],
+            5.0,
+        );
+        assert!(single[0].significant);
+        let report = markdown_report(&single, &[], "ns", 5.0);
+        assert!(report
+            .contains("| `simple_benchmark_BM_Foo` | 1000.00 | 1200.00 | +20.0% | * | slower |"));
+        let unchanged = compare_results(
+            &[results_with_cpu_time(1000.0)],
+            &[results_with_cpu_time(1010.0)],
+            5.0,
+        );
+        assert!(!unchanged[0].significant);
+    }
+}
@@ -0,0 +1,213 @@
+use crate::parse::*;
+
+use chrono::{Datelike, Duration};
+use std::collections::BTreeMap;
+
+pub const RESAMPLE_PERIODS: &[&str] = &["daily", "weekly"];
+pub const RESAMPLE_STATISTICS: &[&str] = &["median", "min", "max"];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResamplePeriod {
+    Daily,
+    Weekly,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ResampleStatistic {
+    Median,
+    Min,
+    Max,
+}
+
+/// Reduces the cpu times of each benchmark to one value per period, e.g. for plotting
+/// years of nightly results.
+#[derive(Clone, Copy, Debug)]
+pub struct Resampling {
+    pub period: ResamplePeriod,
+    pub statistic: ResampleStatistic,
+}
+
+/// Aggregated cpu time of one benchmark within one period.
+#[derive(Clone, PartialEq, Debug)]
+pub struct ResampledPoint {
+    pub exe_name: String,
+    pub benchmark_name: String,
+    /// First day of the period, e.g. the monday for weekly periods
+    pub period_start: String,
+    pub cpu_time_ns: f64,
+    pub sample_count: u64,
+}
+
+impl ResamplePeriod {
+    pub fn from_name(name: &str) -> Option<ResamplePeriod> {
+        match name {
+            "daily" => Some(ResamplePeriod::Daily),
+            "weekly" => Some(ResamplePeriod::Weekly),
+            _ => None,
+        }
+    }
+}
+
+impl ResampleStatistic {
+    pub fn from_name(name: &str) -> Option<ResampleStatistic> {
+        match name {
+            "median" => Some(ResampleStatistic::Median),
+            "min" => Some(ResampleStatistic::Min),
+            "max" => Some(ResampleStatistic::Max),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResampleStatistic::Median => "median",
+            ResampleStatistic::Min => "min",
+            ResampleStatistic::Max => "max",
+        }
+    }
+
+    /// Reduces the samples to a single value, the samples get sorted.
+    pub fn apply(&self, samples: &mut [f64]) -> f64 {
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        match self {
+            ResampleStatistic::Median => {
+                let middle = samples.len() / 2;
+                if samples.len() % 2 == 1 {
+                    samples[middle]
+                } else {
+                    (samples[middle - 1] + samples[middle]) / 2.0
+                }
+            }
+            ResampleStatistic::Min => samples[0],
+            ResampleStatistic::Max => samples[samples.len() - 1],
+        }
+    }
+}
+
+/// Collects the samples of all entries for backends without server-side aggregation.
+pub struct Resampler {
+    resampling: Resampling,
+    samples: BTreeMap<(String, String, String), Vec<f64>>,
+}
+
+impl Resampler {
+    pub fn new(resampling: Resampling) -> Resampler {
+        Self {
+            resampling,
+            samples: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_entry(&mut self, entry: &DataBaseEntry) {
+        let period_start = period_start(&entry.results.context.date, self.resampling.period);
+        for benchmark in &entry.results.benchmarks {
+            self.samples
+                .entry((
+                    entry.exe_name.clone(),
+                    benchmark.name.clone(),
+                    period_start.clone(),
+                ))
+                .or_default()
+                .push(to_nanos(benchmark.cpu_time, benchmark.time_unit.as_deref()));
+        }
+    }
+
+    /// Returns the aggregated points ordered by period.
+    pub fn finish(self) -> Vec<ResampledPoint> {
+        let statistic = self.resampling.statistic;
+        let mut points: Vec<ResampledPoint> = self
+            .samples
+            .into_iter()
+            .map(
+                |((exe_name, benchmark_name, period_start), mut samples)| ResampledPoint {
+                    exe_name,
+                    benchmark_name,
+                    period_start,
+                    cpu_time_ns: statistic.apply(&mut samples),
+                    sample_count: samples.len() as u64,
+                },
+            )
+            .collect();
+        points.sort_by(|a, b| a.period_start.cmp(&b.period_start));
+        points
+    }
+}
+
+/// Uses the date in UTC like the date filters, results with an unknown date format get
+/// a period of their own.
+pub fn period_start(date: &str, period: ResamplePeriod) -> String {
+    let day = match parse_timestamp(date) {
+        Some(timestamp) => timestamp.date_naive(),
+        None => return date.to_string(),
+    };
+    let start = match period {
+        ResamplePeriod::Daily => day,
+        ResamplePeriod::Weekly => day - Duration::days(day.weekday().num_days_from_monday() as i64),
+    };
+    start.format("%Y-%m-%d").to_string()
+}
+
+pub fn to_nanos(time: f64, time_unit: Option<&str>) -> f64 {
+    match time_unit {
+        Some("us") => time * 1e3,
+        Some("ms") => time * 1e6,
+        Some("s") => time * 1e9,
+        _ => time,
+    }
+}
+
+pub fn from_nanos(time_ns: f64, time_unit: &str) -> f64 {
+    match time_unit {
+        "ns" => time_ns,
+        "us" => time_ns / 1e3,
+        "ms" => time_ns / 1e6,
+        _ => panic!("Unknown time unit provided!"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::test_entry;
+
+    #[test]
+    fn test_weekly_median() {
+        let mut resampler = Resampler::new(Resampling {
+            period: ResamplePeriod::Weekly,
+            statistic: ResampleStatistic::Median,
+        });
+        // friday, sunday and monday of the following week
+        for (date, cpu_time) in [
+            ("2021-01-01T23:00:00+01:00", 1.0),
+            ("2021-01-03T00:00:00+00:00", 3.0),
+            ("2021-01-04T00:00:00+00:00", 5.0),
+        ] {
+            let mut entry = test_entry("simple_benchmark", "");
+            entry.results.context.date = date.to_string();
+            entry.results.benchmarks.truncate(1);
+            entry.results.benchmarks[0].cpu_time = cpu_time;
+            entry.results.benchmarks[0].time_unit = Some("us".to_string());
+            resampler.add_entry(&entry);
+        }
+
+        let points = resampler.finish();
+        assert_eq!(points.len(), 2);
+        assert_eq!(points[0].period_start, "2020-12-28");
+        assert_eq!(points[0].cpu_time_ns, 2000.0);
+        assert_eq!(points[0].sample_count, 2);
+        assert_eq!(points[1].period_start, "2021-01-04");
+        assert_eq!(points[1].cpu_time_ns, 5000.0);
+    }
+
+    #[test]
+    fn test_period_start_of_old_date_format() {
+        // a sunday
+        let date = "01/03/21 12:00:00";
+        assert_eq!(period_start(date, ResamplePeriod::Daily), "2021-01-03");
+        assert_eq!(period_start(date, ResamplePeriod::Weekly), "2020-12-28");
+        assert_eq!(
+            period_start("2021-01-04T00:30:00+01:00", ResamplePeriod::Daily),
+            "2021-01-03"
+        );
+    }
+}
@@ -0,0 +1,92 @@
+use crate::parse::*;
+use crate::storage::*;
+
+use chrono::{Duration, Utc};
+use std::collections::BTreeMap;
+
+pub enum PrunePolicy {
+    KeepLast(usize),
+    DropUntaggedOlderThan(i64),
+}
+
+/// Translates a prune policy into filters selecting the entries to delete.
+pub fn prune_filters(storage: &dyn Storage, policy: &PrunePolicy) -> Vec<EntryFilter> {
+    match policy {
+        PrunePolicy::KeepLast(keep_count) => {
+            let all_entries = storage.fetch(&EntryFilter::All(vec![]), None);
+            keep_last_filters(&all_entries, *keep_count)
+        }
+        PrunePolicy::DropUntaggedOlderThan(days) => {
+            let cutoff_date = (Utc::now() - Duration::days(*days))
+                .format("%Y-%m-%d")
+                .to_string();
+            vec![EntryFilter::All(vec![
+                EntryFilter::Untagged,
+                EntryFilter::Before(cutoff_date),
+            ])]
+        }
+    }
+}
+
+/// Selects everything older than the `keep_count` most recent entries of each executable.
+/// Entries sharing the date of the oldest kept entry are kept as well.
+fn keep_last_filters(entries: &[DataBaseEntry], keep_count: usize) -> Vec<EntryFilter> {
+    let mut dates_per_exe: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for entry in entries {
+        dates_per_exe
+            .entry(entry.exe_name.as_str())
+            .or_default()
+            .push(entry.results.context.date.as_str());
+    }
+
+    let mut filters = vec![];
+    for (exe_name, mut dates) in dates_per_exe {
+        if dates.len() <= keep_count {
+            continue;
+        }
+        dates.sort_by_cached_key(|date| std::cmp::Reverse(utc_date(date)));
+        let oldest_kept_date = if keep_count == 0 {
+            // select all dates
+            "~"
+        } else {
+            dates[keep_count - 1]
+        };
+        filters.push(EntryFilter::All(vec![
+            EntryFilter::ExeName(format!("^{}$", regex::escape(exe_name))),
+            EntryFilter::Before(oldest_kept_date.to_string()),
+        ]));
+    }
+    filters
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{test_entry, MemoryStorage};
+
+    #[test]
+    fn test_keep_last_policy() {
+        let storage = MemoryStorage::default();
+        for day in 1..=5 {
+            let mut entry = test_entry("simple_benchmark", "");
+            entry.results.context.date = format!("2021-01-0{}T00:00:00+00:00", day);
+            storage.push(&[entry], DuplicatePolicy::Skip);
+        }
+        storage.push(
+            &[test_entry("with_arg_list_benchmark", "")],
+            DuplicatePolicy::Skip,
+        );
+
+        for filter in prune_filters(&storage, &PrunePolicy::KeepLast(2)) {
+            storage.delete(&filter);
+        }
+
+        let remaining = storage.fetch(&EntryFilter::All(vec![]), None);
+        assert_eq!(remaining.len(), 3);
+        assert_eq!(remaining[0].exe_name, "with_arg_list_benchmark");
+        assert_eq!(
+            remaining[1].results.context.date,
+            "2021-01-04T00:00:00+00:00"
+        );
+    }
+}
@@ -0,0 +1,457 @@
+use crate::config::*;
+use crate::logger::*;
+
+use clap::ArgMatches;
+use colored::*;
+use serde::Deserialize;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+pub const PROJECT_CONFIG_FILE_NAME: &str = ".beast.yaml";
+
+pub const DEFAULT_FILTER: &str = ".*benchmark[^.]*$";
+pub const DEFAULT_TIME_UNIT: &str = "us";
+pub const DEFAULT_XTITLE: &str = "N";
+pub const DEFAULT_THRESHOLD: &str = "5";
+pub const TIME_UNITS: &[&str] = &["ms", "us", "ns"];
+
+const ROOT_DIRS_ENV: &str = "BEAST_DIR";
+const FILTER_ENV: &str = "BEAST_FILTER";
+const BENCHMARK_ARGS_ENV: &str = "BEAST_BENCHMARK_ARGS";
+const TIME_UNIT_ENV: &str = "BEAST_TIMEUNIT";
+const XTITLE_ENV: &str = "BEAST_XTITLE";
+const LINEPLOT_ENV: &str = "BEAST_LINEPLOT";
+const NOPLOT_ENV: &str = "BEAST_NOPLOT";
+const THRESHOLD_ENV: &str = "BEAST_THRESHOLD";
+const READ_ONLY_ENV: &str = "BEAST_READ_ONLY";
+
+/// Layer a setting was taken from, in increasing order of precedence.
+#[derive(Clone, PartialEq, Debug)]
+pub enum ValueSource {
+    Default,
+    UserPreferences,
+    ProjectFile,
+    Environment(String),
+    CommandLine,
+}
+
+impl fmt::Display for ValueSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueSource::Default => write!(f, "default"),
+            ValueSource::UserPreferences => write!(f, "user preferences"),
+            ValueSource::ProjectFile => write!(f, "{}", PROJECT_CONFIG_FILE_NAME),
+            ValueSource::Environment(var) => write!(f, "environment ({})", var),
+            ValueSource::CommandLine => write!(f, "command line"),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Setting<T> {
+    pub value: T,
+    pub source: ValueSource,
+}
+
+/// Contents of a project's `.beast.yaml`, all keys are optional. Relative paths are
+/// relative to the directory of the file.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    pub root_dirs: Option<Vec<PathBuf>>,
+    pub filter: Option<String>,
+    pub benchmark_args: Option<Vec<String>>,
+    pub time_unit: Option<String>,
+    pub xtitle: Option<String>,
+    pub lineplot: Option<bool>,
+    pub noplot: Option<bool>,
+    pub threshold: Option<f64>,
+    pub read_only: Option<bool>,
+    pub profile: Option<String>,
+    pub db: Option<ProjectDbConfig>,
+}
+
+/// Database target of a project, overrides the values of the selected profile.
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectDbConfig {
+    pub backend: Option<String>,
+    pub uri: Option<String>,
+    pub name: Option<String>,
+    pub collection: Option<String>,
+    pub sqlite_path: Option<PathBuf>,
+    pub json_dir: Option<PathBuf>,
+}
+
+/// Settings of a beast invocation, layered as defaults < user preferences < project file
+/// < environment < command line. The stored config keys are layered by `AppConfig`.
+pub struct Settings {
+    pub project_file: Option<PathBuf>,
+    pub profile: Setting<String>,
+    pub root_dirs: Setting<Vec<PathBuf>>,
+    pub filter: Setting<String>,
+    pub benchmark_args: Setting<Vec<String>>,
+    pub time_unit: Setting<String>,
+    pub xtitle: Setting<String>,
+    pub lineplot: Setting<bool>,
+    pub noplot: Setting<bool>,
+    pub threshold: Setting<f64>,
+    pub read_only: Setting<bool>,
+    project: ProjectConfig,
+}
+
+impl Settings {
+    pub fn resolve(matches: &ArgMatches) -> Settings {
+        let current_dir = match std::env::current_dir() {
+            Ok(path_buf) => path_buf,
+            Err(err) => panic!("Can't retrieve current directory: {:?}", err),
+        };
+        let project_file = find_project_config(&current_dir);
+        let project = match &project_file {
+            Some(path) => load_project_config(path),
+            None => ProjectConfig::default(),
+        };
+
+        // the global option is only set in the matches of the subcommand it follows
+        let cli_profile = matches
+            .value_of("profile")
+            .or_else(|| {
+                matches
+                    .subcommand()
+                    .1
+                    .and_then(|submatches| submatches.value_of("profile"))
+            })
+            .map(|profile| profile.to_string());
+        let report_matches = matches.subcommand_matches("report");
+
+        let settings = Settings {
+            profile: layered(
+                cli_profile,
+                env_value(PROFILE_ENV),
+                project.profile.clone(),
+                DEFAULT_PROFILE.to_string(),
+            ),
+            root_dirs: layered(
+                cli_value(matches, "rootdir").map(|dir| vec![PathBuf::from(dir)]),
+                std::env::var_os(ROOT_DIRS_ENV)
+                    .map(|dirs| (ROOT_DIRS_ENV, std::env::split_paths(&dirs).collect())),
+                project.root_dirs.clone(),
+                vec![current_dir],
+            ),
+            filter: layered(
+                cli_value(matches, "filter"),
+                env_value(FILTER_ENV),
+                project.filter.clone(),
+                DEFAULT_FILTER.to_string(),
+            ),
+            benchmark_args: layered(
+                cli_value(matches, "benchmark_args").map(|args| split_args(&args)),
+                std::env::var(BENCHMARK_ARGS_ENV)
+                    .ok()
+                    .map(|args| (BENCHMARK_ARGS_ENV, split_args(&args))),
+                project.benchmark_args.clone(),
+                vec![],
+            ),
+            time_unit: layered(
+                cli_value(matches, "timeunit"),
+                env_value(TIME_UNIT_ENV),
+                project.time_unit.clone(),
+                DEFAULT_TIME_UNIT.to_string(),
+            ),
+            xtitle: layered(
+                cli_value(matches, "xtitle"),
+                env_value(XTITLE_ENV),
+                project.xtitle.clone(),
+                DEFAULT_XTITLE.to_string(),
+            ),
+            lineplot: layered(
+                cli_flag(matches, "lineplot"),
+                env_value(LINEPLOT_ENV),
+                project.lineplot,
+                false,
+            ),
+            noplot: layered(
+                cli_flag(matches, "noplot"),
+                env_value(NOPLOT_ENV),
+                project.noplot,
+                false,
+            ),
+            threshold: layered(
+                report_matches
+                    .and_then(|submatches| cli_value(submatches, "threshold"))
+                    .map(|threshold| parse_value("--threshold", &threshold)),
+                env_value(THRESHOLD_ENV),
+                project.threshold,
+                DEFAULT_THRESHOLD.parse().unwrap(),
+            ),
+            read_only: layered(
+                cli_flag(matches, "read_only").or_else(|| {
+                    matches
+                        .subcommand()
+                        .1
+                        .and_then(|submatches| cli_flag(submatches, "read_only"))
+                }),
+                env_value(READ_ONLY_ENV),
+                project.read_only,
+                false,
+            ),
+            project_file,
+            project,
+        };
+
+        if !TIME_UNITS.contains(&settings.time_unit.value.as_str()) {
+            error_and_exit(
+                &format!(
+                    "Invalid time unit '{}' from {}",
+                    settings.time_unit.value, settings.time_unit.source
+                ),
+                &std::io::Error::new(
+                    std::io::ErrorKind::InvalidInput,
+                    format!("possible values are: {}", TIME_UNITS.join(", ")),
+                ),
+            );
+        }
+        settings
+    }
+
+    /// Overrides the database target of the profile with the one of the project file.
+    pub fn apply_project_db_config(&self, config: &mut AppConfig) {
+        let db = match &self.project.db {
+            Some(db) => db,
+            None => return,
+        };
+        let path_to_string = |path: &PathBuf| path.to_string_lossy().into_owned();
+        let values = [
+            (DATABASE_BACKEND_KEY, db.backend.clone()),
+            (DATABASE_URI_KEY, db.uri.clone()),
+            (DATABASE_NAME_KEY, db.name.clone()),
+            (DATABASE_BENCHMARK_COLLECTION_KEY, db.collection.clone()),
+            (
+                DATABASE_SQLITE_PATH_KEY,
+                db.sqlite_path.as_ref().map(path_to_string),
+            ),
+            (
+                DATABASE_JSON_DIR_PATH_KEY,
+                db.json_dir.as_ref().map(path_to_string),
+            ),
+        ];
+        for (key, value) in values {
+            if let Some(value) = value {
+                config.override_value(key, &value, ValueSource::ProjectFile);
+            }
+        }
+    }
+
+    pub fn print_explanation(&self, config: &AppConfig) {
+        match &self.project_file {
+            Some(path) => println!("Project file: {}", path.to_string_lossy()),
+            None => println!(
+                "Project file: none ({} not found in the current directory or its parents)",
+                PROJECT_CONFIG_FILE_NAME
+            ),
+        }
+
+        let root_dirs: Vec<String> = self
+            .root_dirs
+            .value
+            .iter()
+            .map(|dir| dir.to_string_lossy().into_owned())
+            .collect();
+        let mut rows = vec![
+            explain_row("profile", &self.profile.value, &self.profile.source),
+            explain_row("root_dirs", &root_dirs.join(", "), &self.root_dirs.source),
+            explain_row("filter", &self.filter.value, &self.filter.source),
+            explain_row(
+                "benchmark_args",
+                &self.benchmark_args.value.join(" "),
+                &self.benchmark_args.source,
+            ),
+            explain_row("time_unit", &self.time_unit.value, &self.time_unit.source),
+            explain_row("xtitle", &self.xtitle.value, &self.xtitle.source),
+            explain_row(
+                "lineplot",
+                &self.lineplot.value.to_string(),
+                &self.lineplot.source,
+            ),
+            explain_row(
+                "noplot",
+                &self.noplot.value.to_string(),
+                &self.noplot.source,
+            ),
+            explain_row(
+                "threshold",
+                &self.threshold.value.to_string(),
+                &self.threshold.source,
+            ),
+            explain_row(
+                "read_only",
+                &self.read_only.value.to_string(),
+                &self.read_only.source,
+            ),
+        ];
+        for (key, value, source) in config.explain() {
+            rows.push(explain_row(&key, &value, &source));
+        }
+
+        let key_width = rows.iter().map(|row| row.0.len()).max().unwrap_or(0);
+        let value_width = rows.iter().map(|row| row.1.len()).max().unwrap_or(0);
+        println!();
+        for (key, value, source) in rows {
+            println!(
+                "{:<key_width$}  {:<value_width$}  {}",
+                key,
+                value,
+                source.dimmed(),
+                key_width = key_width,
+                value_width = value_width
+            );
+        }
+    }
+}
+
+/// Looks for a project file in the given directory and all its parents.
+pub fn find_project_config(start_dir: &Path) -> Option<PathBuf> {
+    start_dir
+        .ancestors()
+        .map(|dir| dir.join(PROJECT_CONFIG_FILE_NAME))
+        .find(|path| path.is_file())
+}
+
+fn load_project_config(path: &Path) -> ProjectConfig {
+    match File::open(path) {
+        Ok(f) => match serde_yaml::from_reader::<_, ProjectConfig>(BufReader::new(f)) {
+            Ok(mut project_config) => {
+                let project_dir = path.parent().unwrap();
+                let resolve = |path: &mut PathBuf| *path = project_dir.join(&path);
+                project_config
+                    .root_dirs
+                    .iter_mut()
+                    .flatten()
+                    .for_each(resolve);
+                if let Some(db) = project_config.db.as_mut() {
+                    db.sqlite_path.iter_mut().for_each(resolve);
+                    db.json_dir.iter_mut().for_each(resolve);
+                }
+                project_config
+            }
+            Err(e) => error_and_exit(
+                &format!("{} has invalid format", path.to_string_lossy()),
+                &e,
+            ),
+        },
+        Err(e) => error_and_exit(&format!("Could not open {}", path.to_string_lossy()), &e),
+    }
+}
+
+/// Takes the value of the layer with the highest precedence.
+fn layered<T>(
+    cli: Option<T>,
+    env: Option<(&str, T)>,
+    project: Option<T>,
+    default: T,
+) -> Setting<T> {
+    if let Some(value) = cli {
+        return Setting {
+            value,
+            source: ValueSource::CommandLine,
+        };
+    }
+    if let Some((var, value)) = env {
+        return Setting {
+            value,
+            source: ValueSource::Environment(var.to_string()),
+        };
+    }
+    match project {
+        Some(value) => Setting {
+            value,
+            source: ValueSource::ProjectFile,
+        },
+        None => Setting {
+            value: default,
+            source: ValueSource::Default,
+        },
+    }
+}
+
+/// Only explicitly given options count, clap's default values are the lowest layer.
+fn cli_value(matches: &ArgMatches, name: &str) -> Option<String> {
+    if matches.occurrences_of(name) > 0 {
+        matches.value_of(name).map(|value| value.to_string())
+    } else {
+        None
+    }
+}
+
+fn cli_flag(matches: &ArgMatches, name: &str) -> Option<bool> {
+    if matches.is_present(name) {
+        Some(true)
+    } else {
+        None
+    }
+}
+
+fn env_value<T: FromStr>(var: &str) -> Option<(&str, T)>
+where
+    T::Err: std::error::Error,
+{
+    std::env::var(var)
+        .ok()
+        .map(|value| (var, parse_value(var, &value)))
+}
+
+fn parse_value<T: FromStr>(name: &str, value: &str) -> T
+where
+    T::Err: std::error::Error,
+{
+    match value.parse::<T>() {
+        Ok(value) => value,
+        Err(e) => error_and_exit(&format!("Invalid value '{}' for {}", value, name), &e),
+    }
+}
+
+fn split_args(args: &str) -> Vec<String> {
+    args.split_whitespace().map(|arg| arg.to_string()).collect()
+}
+
+fn explain_row(key: &str, value: &str, source: &ValueSource) -> (String, String, String) {
+    (key.to_string(), value.to_string(), source.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_project_config_in_parent() {
+        let project_dir =
+            std::env::temp_dir().join(format!("beast_project_test_{}", std::process::id()));
+        let nested_dir = project_dir.join("build").join("benchmarks");
+        std::fs::create_dir_all(&nested_dir).unwrap();
+        let project_file = project_dir.join(PROJECT_CONFIG_FILE_NAME);
+        std::fs::write(
+            &project_file,
+            "root_dirs: [build]\nfilter: \"_bench$\"\ntime_unit: ms\ndb:\n  name: team\n",
+        )
+        .unwrap();
+
+        assert_eq!(find_project_config(&nested_dir), Some(project_file.clone()));
+        let project = load_project_config(&project_file);
+        assert_eq!(project.filter.as_deref(), Some("_bench$"));
+        assert_eq!(project.db.unwrap().name.as_deref(), Some("team"));
+
+        let setting = layered(None, None, project.time_unit, "us".to_string());
+        assert_eq!(setting.value, "ms");
+        assert_eq!(setting.source, ValueSource::ProjectFile);
+        let setting = layered(None, Some((TIME_UNIT_ENV, "ns")), Some("ms"), "us");
+        assert_eq!(
+            setting.source,
+            ValueSource::Environment(TIME_UNIT_ENV.into())
+        );
+
+        std::fs::remove_dir_all(project_dir).unwrap();
+    }
+}
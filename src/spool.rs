@@ -0,0 +1,246 @@
+use crate::config::*;
+use crate::logger::*;
+use crate::parse::*;
+use crate::storage::*;
+
+use mongodb::error::ErrorKind;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+static SPOOL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Local queue for pushes which could not be stored because the database was unreachable.
+/// Every push is saved as a single json file, the file names keep the order of the pushes.
+pub struct Spool {
+    dir: PathBuf,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct SpooledPush {
+    duplicates: DuplicatePolicy,
+    entries: Vec<DataBaseEntry>,
+}
+
+impl Spool {
+    /// Every profile has its own spool, so spooled results end up in the database of the
+    /// profile they were pushed with.
+    pub fn open(config: &AppConfig) -> Spool {
+        let mut spool_dir = preferences::prefs_base_dir().unwrap();
+        spool_dir.push(Path::new("beastspool"));
+        if config.profile() != DEFAULT_PROFILE {
+            spool_dir.push(Path::new("profiles"));
+            spool_dir.push(Path::new(config.profile()));
+        }
+        Self::with_dir(&spool_dir)
+    }
+
+    fn with_dir(dir: &Path) -> Spool {
+        Self {
+            dir: dir.to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn enqueue(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) {
+        fs::create_dir_all(&self.dir).expect("Could not create spool directory!");
+
+        let since_epoch = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let file_name = format!(
+            "{:020}_{:09}_{}_{}",
+            since_epoch.as_secs(),
+            since_epoch.subsec_nanos(),
+            std::process::id(),
+            SPOOL_COUNTER.fetch_add(1, Ordering::SeqCst)
+        );
+        let spooled_push = SpooledPush {
+            duplicates,
+            entries: entries.to_vec(),
+        };
+
+        // only complete files have the json extension and are picked up by flush
+        let temp_path = self.dir.join(file_name.clone() + ".tmp");
+        let f = File::create(&temp_path)
+            .unwrap_or_else(|_| panic!("Could not create file {}!", temp_path.to_string_lossy()));
+        serde_json::to_writer(&f, &spooled_push)
+            .unwrap_or_else(|_| panic!("Could not write to file {}!", temp_path.to_string_lossy()));
+        fs::rename(&temp_path, self.dir.join(file_name + ".json"))
+            .expect("Could not write to spool directory!");
+    }
+
+    /// Returns the number of spooled pushes.
+    pub fn pending_count(&self) -> usize {
+        self.pending_files().len()
+    }
+
+    /// Pushes all spooled pushes in order and returns the number of stored entries.
+    /// Pushes are idempotent per run, so an interrupted flush can simply be repeated.
+    pub fn flush(&self, storage: &dyn Storage) -> u64 {
+        let mut stored_count = 0;
+        for file_path in self.pending_files() {
+            let spooled_push: SpooledPush = match File::open(&file_path) {
+                Ok(f) => serde_json::from_reader(BufReader::new(f))
+                    .unwrap_or_else(|e| error_and_exit("Spooled push has invalid format", &e)),
+                // flushed by a concurrent process in the meantime
+                Err(_) => continue,
+            };
+            stored_count += storage.push(&spooled_push.entries, spooled_push.duplicates);
+            let _ = fs::remove_file(&file_path);
+        }
+        stored_count
+    }
+
+    fn pending_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = match fs::read_dir(&self.dir) {
+            Ok(dir_entries) => dir_entries
+                .filter_map(|dir_entry| dir_entry.ok().map(|dir_entry| dir_entry.path()))
+                .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+                .collect(),
+            Err(_) => vec![],
+        };
+        files.sort();
+        files
+    }
+}
+
+/// Stores the pushes which were spooled while the database was unreachable, in
+/// read-only mode they are kept for a later `beast dbsync`.
+pub fn flush_spool(config: &AppConfig, storage: &dyn Storage) {
+    let spool = Spool::open(config);
+    let pending_count = spool.pending_count();
+    if pending_count > 0 && config.is_read_only() {
+        eprintln!(
+            "Keeping {} spooled pushes in read-only mode, use 'beast dbsync' to push them.",
+            pending_count
+        );
+    } else if pending_count > 0 {
+        eprintln!("Pushing {} spooled pushes ...", pending_count);
+        let stored_count = spool.flush(storage);
+        eprintln!("Pushed {} spooled results.", stored_count);
+    }
+}
+
+/// Whether opening the storage failed because the database could not be reached, in
+/// contrast to e.g. invalid credentials or an invalid config which a retry won't fix.
+fn is_unreachable(error: &(dyn Error + 'static)) -> bool {
+    if let Some(mongodb_error) = error.downcast_ref::<mongodb::error::Error>() {
+        return matches!(
+            mongodb_error.kind.as_ref(),
+            ErrorKind::ServerSelectionError { .. } | ErrorKind::Io(_)
+        );
+    }
+    error.is::<std::io::Error>()
+}
+
+/// Pushes to the configured database and returns the number of stored entries. Earlier
+/// spooled pushes are stored first. If the database is unreachable, the push is spooled
+/// for a later `beast dbsync` instead, in read-only mode beast exits. Other errors are
+/// not resolved by retrying, so beast exits for them as well.
+pub fn push_or_spool(
+    config: &AppConfig,
+    entries: &[DataBaseEntry],
+    duplicates: DuplicatePolicy,
+) -> Option<u64> {
+    try_push_or_spool(config, entries, duplicates)
+        .unwrap_or_else(|e| error_and_exit("Could not connect to database", e.as_ref()))
+}
+
+/// Like `push_or_spool`, but returns the errors instead of exiting, e.g. to clean up first.
+pub fn try_push_or_spool(
+    config: &AppConfig,
+    entries: &[DataBaseEntry],
+    duplicates: DuplicatePolicy,
+) -> Result<Option<u64>, Box<dyn Error>> {
+    match try_open_storage(config) {
+        Ok(storage) => {
+            flush_spool(config, storage.as_ref());
+            Ok(Some(storage.push(entries, duplicates)))
+        }
+        Err(e) if !is_unreachable(e.as_ref()) => Err(e),
+        Err(e) if config.is_read_only() => {
+            Err(format!("{}, spooling is disabled in read-only mode", e).into())
+        }
+        Err(e) => {
+            let spool = Spool::open(config);
+            spool.enqueue(entries, duplicates);
+            warn(
+                &format!(
+                    "Could not connect to database, spooled {} results in {}. \
+                     Use 'beast dbsync' to push them later",
+                    entries.len(),
+                    spool.dir().to_string_lossy()
+                ),
+                e.as_ref(),
+            );
+            Ok(None)
+        }
+    }
+}
+
+/// Exits right away for the errors pushing would fail with, so long running commands like
+/// repocheck fail before doing any work. Pushes to an unreachable database are spooled.
+pub fn ensure_pushable(config: &AppConfig) {
+    match try_open_storage(config) {
+        Err(e) if !is_unreachable(e.as_ref()) => {
+            error_and_exit("Could not connect to database", e.as_ref())
+        }
+        Err(e) if config.is_read_only() => error_and_exit(
+            "Could not connect to database, spooling is disabled in read-only mode",
+            e.as_ref(),
+        ),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{test_entry, MemoryStorage};
+
+    #[test]
+    fn test_spool_flush_in_order() {
+        let dir = std::env::temp_dir().join(format!("beast_spool_test_{}", std::process::id()));
+        let spool = Spool::with_dir(&dir);
+        assert_eq!(spool.pending_count(), 0);
+
+        let mut entry = test_entry("simple_benchmark", "v1");
+        entry.results.run_id = Some("run1".to_string());
+        spool.enqueue(&[entry.clone()], DuplicatePolicy::Skip);
+        entry.tags = vec!["v2".to_string()];
+        spool.enqueue(&[entry], DuplicatePolicy::Replace);
+        assert_eq!(spool.pending_count(), 2);
+
+        let storage = MemoryStorage::default();
+        assert_eq!(spool.flush(&storage), 2);
+        assert_eq!(spool.pending_count(), 0);
+        let fetched = storage.fetch(&EntryFilter::All(vec![]), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].tags, vec!["v2"]);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_only_unreachable_databases_are_spooled() {
+        let io = mongodb::error::Error::from(ErrorKind::Io(std::io::Error::from(
+            std::io::ErrorKind::ConnectionRefused,
+        )));
+        assert!(is_unreachable(&io));
+        let decode = mongodb::bson::from_document::<DataBaseEntry>(mongodb::bson::Document::new())
+            .unwrap_err();
+        assert!(!is_unreachable(&mongodb::error::Error::from(
+            ErrorKind::BsonDecode(decode)
+        )));
+        assert!(is_unreachable(&std::io::Error::from(
+            std::io::ErrorKind::NotFound
+        )));
+        assert!(!is_unreachable(&rusqlite::Error::InvalidQuery));
+    }
+}
@@ -0,0 +1,475 @@
+use crate::config::*;
+use crate::parse::*;
+use crate::storage::*;
+
+use regex::Regex;
+use rusqlite::functions::FunctionFlags;
+use rusqlite::{params, Connection, NO_PARAMS};
+
+/// The date of the results in UTC (see `utc_date`), used for filtering and ordering.
+const DATE_EXPRESSION: &str = "date_utc";
+
+pub struct SqliteDataBase {
+    connection: Connection,
+}
+
+impl SqliteDataBase {
+    pub fn init(config: &AppConfig) -> rusqlite::Result<SqliteDataBase> {
+        let sqlite_path = config.sqlite_path();
+
+        eprintln!("Opening sqlite database {} ...", sqlite_path);
+
+        Connection::open(sqlite_path).map(Self::with_connection)
+    }
+
+    fn with_connection(connection: Connection) -> SqliteDataBase {
+        // mongodb compatible regex support for "exe_name REGEXP pattern" queries
+        connection
+            .create_scalar_function(
+                "regexp",
+                2,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |ctx| {
+                    // the pattern is compiled once per statement instead of once per row
+                    let re = ctx.get_or_create_aux(
+                        0,
+                        |pattern| -> Result<_, Box<dyn std::error::Error + Send + Sync>> {
+                            Ok(Regex::new(pattern.as_str()?)?)
+                        },
+                    )?;
+                    let text = match ctx.get::<Option<String>>(1)? {
+                        Some(text) => text,
+                        None => return Ok(false),
+                    };
+                    Ok(re.is_match(&text))
+                },
+            )
+            .expect("Could not register regexp function for sqlite database!");
+        connection
+            .create_scalar_function(
+                "utc_date",
+                1,
+                FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DETERMINISTIC,
+                |ctx| Ok(utc_date(&ctx.get::<String>(0)?)),
+            )
+            .expect("Could not register utc_date function for sqlite database!");
+
+        connection
+            .execute_batch(
+                "CREATE TABLE IF NOT EXISTS benchmark_results (
+                    id       INTEGER PRIMARY KEY,
+                    exe_name TEXT NOT NULL,
+                    tag      TEXT NOT NULL,
+                    results  TEXT NOT NULL
+                );
+                CREATE INDEX IF NOT EXISTS benchmark_results_exe_name
+                    ON benchmark_results (exe_name);",
+            )
+            .expect("Could not create sqlite database tables!");
+
+        add_column_if_missing(&connection, "provenance", "TEXT");
+        if add_column_if_missing(&connection, "tags", "TEXT") {
+            // previous versions only stored a single tag
+            connection
+                .execute(
+                    "UPDATE benchmark_results \
+                     SET tags = CASE WHEN tag = '' THEN '[]' ELSE json_array(tag) END",
+                    NO_PARAMS,
+                )
+                .expect("Could not migrate sqlite database table!");
+        }
+        if add_column_if_missing(&connection, "meta", "TEXT") {
+            connection
+                .execute("UPDATE benchmark_results SET meta = '{}'", NO_PARAMS)
+                .expect("Could not migrate sqlite database table!");
+        }
+        if add_column_if_missing(&connection, "run_id", "TEXT") {
+            connection
+                .execute(
+                    "UPDATE benchmark_results SET run_id = coalesce(\
+                     json_extract(provenance, '$.run_id'), json_extract(results, '$.run_id'))",
+                    NO_PARAMS,
+                )
+                .expect("Could not migrate sqlite database table!");
+        }
+        if add_column_if_missing(&connection, "date_utc", "TEXT") {
+            connection
+                .execute(
+                    "UPDATE benchmark_results \
+                     SET date_utc = utc_date(json_extract(results, '$.context.date'))",
+                    NO_PARAMS,
+                )
+                .expect("Could not migrate sqlite database table!");
+        }
+        connection
+            .execute(
+                "CREATE INDEX IF NOT EXISTS benchmark_results_date_utc \
+                 ON benchmark_results (date_utc)",
+                NO_PARAMS,
+            )
+            .expect("Could not create sqlite database index!");
+
+        let database = Self { connection };
+        database.create_run_index();
+        database
+    }
+
+    /// Lets the database enforce unique run IDs per executable. Entries without run ID
+    /// (pushed by previous versions) are excluded from the index.
+    fn create_run_index(&self) {
+        let index_result = self.connection.execute(
+            "CREATE UNIQUE INDEX IF NOT EXISTS benchmark_results_run \
+             ON benchmark_results (exe_name, run_id) WHERE run_id IS NOT NULL",
+            NO_PARAMS,
+        );
+        if let Err(e) = index_result {
+            eprintln!(
+                "Warning: Could not create unique run index, the database might contain \
+                 duplicate runs. Use 'beast dbdedupe' to remove them. ({})",
+                e
+            );
+        }
+    }
+}
+
+impl Storage for SqliteDataBase {
+    fn push(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) -> u64 {
+        // conflicts are detected by the unique run index
+        let conflict_resolution = match duplicates {
+            DuplicatePolicy::Skip => "IGNORE",
+            DuplicatePolicy::Replace => "REPLACE",
+        };
+        let mut stored_count = 0;
+        let transaction = self
+            .connection
+            .unchecked_transaction()
+            .expect("Could not start sqlite transaction!");
+
+        for entry in entries {
+            let results_json = serde_json::to_string(&entry.results).unwrap();
+            let provenance_json = entry
+                .provenance
+                .as_ref()
+                .map(|provenance| serde_json::to_string(provenance).unwrap());
+            let tags_json = serde_json::to_string(&entry.tags).unwrap();
+            let meta_json = serde_json::to_string(&entry.meta).unwrap();
+            // the single "tag" column of previous versions is superseded by "tags"
+            stored_count += transaction
+                .execute(
+                    &format!(
+                        "INSERT OR {} INTO benchmark_results \
+                         (exe_name, tag, tags, meta, results, provenance, run_id, date_utc) \
+                         VALUES (?1, '', ?2, ?3, ?4, ?5, ?6, ?7)",
+                        conflict_resolution
+                    ),
+                    params![
+                        entry.exe_name,
+                        tags_json,
+                        meta_json,
+                        results_json,
+                        provenance_json,
+                        run_id(entry),
+                        utc_date(&entry.results.context.date)
+                    ],
+                )
+                .expect("Could not insert benchmark results in sqlite database!")
+                as u64;
+        }
+
+        transaction
+            .commit()
+            .expect("Could not commit benchmark results to sqlite database!");
+        stored_count
+    }
+
+    fn fetch(&self, entry_filter: &EntryFilter, last: Option<usize>) -> Vec<DataBaseEntry> {
+        let (condition, params) = filter_condition(entry_filter);
+        // for "last" fetch the most recent entries and restore the date order afterwards
+        let order_and_limit = match last {
+            Some(last) => format!("ORDER BY {} DESC, id DESC LIMIT {}", DATE_EXPRESSION, last),
+            None => format!("ORDER BY {}, id", DATE_EXPRESSION),
+        };
+        let query = format!(
+            "SELECT exe_name, tags, meta, results, provenance FROM benchmark_results WHERE {} {}",
+            condition, order_and_limit
+        );
+        eprintln!("Using sqlite query: {}", query);
+
+        let mut statement = self
+            .connection
+            .prepare(&query)
+            .expect("Could not prepare sqlite query!");
+
+        let rows = statement
+            .query_map(params, entry_from_row)
+            .expect("Could not fetch results from sqlite database!");
+
+        let mut fetched_results: Vec<DataBaseEntry> = rows
+            .map(|row| row.expect("Could not read sqlite database row!"))
+            .collect();
+
+        if last.is_some() {
+            fetched_results.reverse();
+        }
+        retain_matching_benchmarks(&mut fetched_results, entry_filter);
+        fetched_results
+    }
+
+    fn for_each_entry(&self, entry_filter: &EntryFilter, f: &mut dyn FnMut(DataBaseEntry)) {
+        let (condition, params) = filter_condition(entry_filter);
+        let query = format!(
+            "SELECT exe_name, tags, meta, results, provenance FROM benchmark_results WHERE {}",
+            condition
+        );
+        eprintln!("Using sqlite query: {}", query);
+
+        let mut statement = self
+            .connection
+            .prepare(&query)
+            .expect("Could not prepare sqlite query!");
+        // rows are deserialized one at a time instead of collecting the whole result set
+        let rows = statement
+            .query_map(params, entry_from_row)
+            .expect("Could not fetch results from sqlite database!");
+
+        let patterns = benchmark_regexes(entry_filter);
+        for row in rows {
+            let mut entry = row.expect("Could not read sqlite database row!");
+            if retain_benchmarks_of_entry(&mut entry, &patterns) {
+                f(entry);
+            }
+        }
+    }
+
+    fn delete(&self, entry_filter: &EntryFilter) -> u64 {
+        let (condition, params) = filter_condition(entry_filter);
+        let query = format!("DELETE FROM benchmark_results WHERE {}", condition);
+        eprintln!("Using sqlite query: {}", query);
+
+        self.connection
+            .execute(&query, params)
+            .expect("Could not delete results from sqlite database!") as u64
+    }
+
+    fn storage_size(&self) -> Option<u64> {
+        self.connection
+            .query_row(
+                "SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()",
+                NO_PARAMS,
+                |row| row.get::<_, i64>(0),
+            )
+            .ok()
+            .map(|size| size as u64)
+    }
+
+    fn remove_duplicates(&self) -> u64 {
+        let deleted_count =
+            self.connection
+                .execute(
+                    "DELETE FROM benchmark_results WHERE id NOT IN \
+                     (SELECT min(id) FROM benchmark_results \
+                     GROUP BY exe_name, coalesce(run_id, json_extract(results, '$.context.date')))",
+                    NO_PARAMS,
+                )
+                .expect("Could not delete results from sqlite database!") as u64;
+        self.create_run_index();
+        deleted_count
+    }
+}
+
+/// Extends tables created by previous versions, returns if the column had to be added.
+fn add_column_if_missing(connection: &Connection, column: &str, column_type: &str) -> bool {
+    let column_exists: bool = connection
+        .query_row(
+            "SELECT COUNT(*) > 0 FROM pragma_table_info('benchmark_results') WHERE name = ?1",
+            params![column],
+            |row| row.get(0),
+        )
+        .expect("Could not read sqlite table info!");
+    if !column_exists {
+        connection
+            .execute(
+                &format!(
+                    "ALTER TABLE benchmark_results ADD COLUMN {} {}",
+                    column, column_type
+                ),
+                NO_PARAMS,
+            )
+            .expect("Could not migrate sqlite database table!");
+    }
+    !column_exists
+}
+
+/// Reads an entry from the columns exe_name, tags, meta, results and provenance.
+fn entry_from_row(row: &rusqlite::Row) -> rusqlite::Result<DataBaseEntry> {
+    let tags_json: String = row.get(1)?;
+    let meta_json: String = row.get(2)?;
+    let results_json: String = row.get(3)?;
+    let provenance_json: Option<String> = row.get(4)?;
+    Ok(DataBaseEntry {
+        exe_name: row.get(0)?,
+        tags: serde_json::from_str(&tags_json).expect("Could not deserialize database entry!"),
+        meta: serde_json::from_str(&meta_json).expect("Could not deserialize database entry!"),
+        provenance: provenance_json.map(|provenance_json| {
+            serde_json::from_str(&provenance_json).expect("Could not deserialize database entry!")
+        }),
+        results: serde_json::from_str(&results_json)
+            .expect("Could not deserialize database entry!"),
+    })
+}
+
+/// Translates the filter into a sql condition with positional parameters.
+fn filter_condition(entry_filter: &EntryFilter) -> (String, Vec<String>) {
+    match entry_filter {
+        EntryFilter::ExeName(reg_expr) => ("exe_name REGEXP ?".to_string(), vec![reg_expr.clone()]),
+        EntryFilter::Tag(tag) => (
+            "EXISTS (SELECT 1 FROM json_each(tags) WHERE json_each.value = ?)".to_string(),
+            vec![tag.clone()],
+        ),
+        EntryFilter::TagRegex(reg_expr) => (
+            "EXISTS (SELECT 1 FROM json_each(tags) WHERE json_each.value REGEXP ?)".to_string(),
+            vec![reg_expr.clone()],
+        ),
+        EntryFilter::Meta(key, value) => (
+            "json_extract(meta, ?) = ?".to_string(),
+            vec![format!("$.\"{}\"", key), value.clone()],
+        ),
+        EntryFilter::Benchmark(reg_expr) => (
+            "EXISTS (SELECT 1 FROM json_each(results, '$.benchmarks') \
+             WHERE json_extract(json_each.value, '$.name') REGEXP ?)"
+                .to_string(),
+            vec![reg_expr.clone()],
+        ),
+        EntryFilter::Since(date) => (format!("{} >= ?", DATE_EXPRESSION), vec![utc_date(date)]),
+        EntryFilter::Until(date) => (format!("{} < ?", DATE_EXPRESSION), vec![until_bound(date)]),
+        EntryFilter::Before(date) => (format!("{} < ?", DATE_EXPRESSION), vec![utc_date(date)]),
+        EntryFilter::Untagged => ("json_array_length(tags) = 0".to_string(), vec![]),
+        EntryFilter::Host(reg_expr) => (
+            "json_extract(provenance, '$.host_name') REGEXP ? \
+             OR json_extract(results, '$.context.host_name') REGEXP ?"
+                .to_string(),
+            vec![reg_expr.clone(), reg_expr.clone()],
+        ),
+        EntryFilter::Provenance(field, reg_expr) => (
+            format!("json_extract(provenance, '$.{}') REGEXP ?", field.key()),
+            vec![reg_expr.clone()],
+        ),
+        EntryFilter::All(filters) => {
+            if filters.is_empty() {
+                return ("1".to_string(), vec![]);
+            }
+            let mut conditions = vec![];
+            let mut params = vec![];
+            for filter in filters {
+                let (condition, mut filter_params) = filter_condition(filter);
+                conditions.push(format!("({})", condition));
+                params.append(&mut filter_params);
+            }
+            (conditions.join(" AND "), params)
+        }
+        EntryFilter::Any(filters) => {
+            if filters.is_empty() {
+                return ("0".to_string(), vec![]);
+            }
+            let mut conditions = vec![];
+            let mut params = vec![];
+            for filter in filters {
+                let (condition, mut filter_params) = filter_condition(filter);
+                conditions.push(format!("({})", condition));
+                params.append(&mut filter_params);
+            }
+            (conditions.join(" OR "), params)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::tests::{entries_with_offsets, test_entry};
+
+    #[test]
+    fn test_sqlite_storage() {
+        let storage = SqliteDataBase::with_connection(Connection::open_in_memory().unwrap());
+        let mut entry_with_meta = test_entry("with_arg_list_benchmark", "");
+        entry_with_meta
+            .meta
+            .insert("compiler".to_string(), "gcc12".to_string());
+        storage.push(
+            &[test_entry("simple_benchmark", "v1"), entry_with_meta],
+            DuplicatePolicy::Skip,
+        );
+
+        let fetched = storage.fetch(&EntryFilter::ExeName("^simple.*".to_string()), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].exe_name, "simple_benchmark");
+        assert_eq!(fetched[0].tags, vec!["v1"]);
+        assert!(storage.storage_size().unwrap() > 0);
+
+        let meta = EntryFilter::Meta("compiler".to_string(), "gcc12".to_string());
+        assert_eq!(
+            storage.fetch(&meta, None)[0].exe_name,
+            "with_arg_list_benchmark"
+        );
+
+        let combined = EntryFilter::All(vec![
+            EntryFilter::TagRegex("^v".to_string()),
+            EntryFilter::Until("2021-01-01".to_string()),
+            EntryFilter::Benchmark("Bar".to_string()),
+        ]);
+        let fetched = storage.fetch(&combined, Some(1));
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].results.benchmarks[0].name, "BM_Bar");
+
+        let mut streamed = vec![];
+        storage.for_each_entry(&combined, &mut |entry| streamed.push(entry));
+        assert_eq!(streamed.len(), 1);
+        assert!(streamed
+            .iter()
+            .all(|entry| entry.results.benchmarks.len() == 1));
+
+        assert_eq!(storage.delete(&EntryFilter::ExeName(".*".to_string())), 2);
+        assert!(storage
+            .fetch(&EntryFilter::ExeName(".*".to_string()), None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_sqlite_dates_in_utc() {
+        let storage = SqliteDataBase::with_connection(Connection::open_in_memory().unwrap());
+        let mut entries = entries_with_offsets();
+        entries.reverse();
+        storage.push(&entries, DuplicatePolicy::Skip);
+
+        let tags = |entries: Vec<DataBaseEntry>| -> Vec<String> {
+            entries.into_iter().flat_map(|entry| entry.tags).collect()
+        };
+        let all = EntryFilter::All(vec![]);
+        assert_eq!(
+            tags(storage.fetch(&all, None)),
+            vec!["local", "east", "west"]
+        );
+        assert_eq!(tags(storage.fetch(&all, Some(1))), vec!["west"]);
+        let until = EntryFilter::Until("2021-01-01".to_string());
+        assert_eq!(tags(storage.fetch(&until, None)), vec!["local", "east"]);
+
+        let overlapping = EntryFilter::Any(vec![until, EntryFilter::Tag("east".to_string())]);
+        assert_eq!(storage.delete(&overlapping), 2);
+        assert!(storage.delete(&EntryFilter::Any(vec![])) == 0);
+    }
+
+    #[test]
+    fn test_sqlite_unique_runs() {
+        let storage = SqliteDataBase::with_connection(Connection::open_in_memory().unwrap());
+        let mut entry = test_entry("simple_benchmark", "v1");
+        entry.results.run_id = Some("run1".to_string());
+        assert_eq!(storage.push(&[entry.clone()], DuplicatePolicy::Skip), 1);
+        assert_eq!(storage.push(&[entry.clone()], DuplicatePolicy::Skip), 0);
+
+        entry.tags = vec!["v2".to_string()];
+        assert_eq!(storage.push(&[entry], DuplicatePolicy::Replace), 1);
+        let fetched = storage.fetch(&EntryFilter::All(vec![]), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].tags, vec!["v2"]);
+    }
+}
@@ -0,0 +1,612 @@
+use crate::config::*;
+use crate::database::*;
+use crate::jsondir::*;
+use crate::logger::*;
+use crate::parse::*;
+use crate::provenance::*;
+use crate::resample::*;
+use crate::sqlite::*;
+
+use colored::*;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::path::Path;
+
+/// Selects stored entries. Dates are compared in UTC (see `utc_date`), date filters also
+/// work on prefixes like "2021-03" or "2021-03-01". In contrast to the inclusive "Until",
+/// "Before" only selects dates strictly lower than the given one.
+pub enum EntryFilter {
+    ExeName(String),
+    Tag(String),
+    TagRegex(String),
+    Meta(String, String),
+    Benchmark(String),
+    Since(String),
+    Until(String),
+    Before(String),
+    Untagged,
+    Host(String),
+    Provenance(ProvenanceField, String),
+    All(Vec<EntryFilter>),
+    /// Matches entries matching at least one of the filters, none if it is empty
+    Any(Vec<EntryFilter>),
+}
+
+impl EntryFilter {
+    /// Evaluates the filter for a single entry, e.g. for backends without own query language.
+    pub fn matches(&self, entry: &DataBaseEntry) -> bool {
+        let context = &entry.results.context;
+        match self {
+            EntryFilter::ExeName(reg_expr) => compile_regex(reg_expr).is_match(&entry.exe_name),
+            EntryFilter::Tag(tag) => entry.tags.contains(tag),
+            EntryFilter::TagRegex(reg_expr) => {
+                let re = compile_regex(reg_expr);
+                entry.tags.iter().any(|tag| re.is_match(tag))
+            }
+            EntryFilter::Meta(key, value) => entry.meta.get(key) == Some(value),
+            EntryFilter::Benchmark(reg_expr) => {
+                let re = compile_regex(reg_expr);
+                entry
+                    .results
+                    .benchmarks
+                    .iter()
+                    .any(|bm| re.is_match(&bm.name))
+            }
+            EntryFilter::Since(date) => utc_date(&context.date) >= utc_date(date),
+            EntryFilter::Until(date) => utc_date(&context.date) < until_bound(date),
+            EntryFilter::Before(date) => utc_date(&context.date) < utc_date(date),
+            EntryFilter::Untagged => entry.tags.is_empty(),
+            EntryFilter::Host(reg_expr) => {
+                let re = compile_regex(reg_expr);
+                let provenance_host_name = entry
+                    .provenance
+                    .as_ref()
+                    .and_then(|provenance| provenance.host_name.as_ref());
+                [provenance_host_name, context.host_name.as_ref()]
+                    .iter()
+                    .flatten()
+                    .any(|host_name| re.is_match(host_name))
+            }
+            EntryFilter::Provenance(field, reg_expr) => match entry
+                .provenance
+                .as_ref()
+                .and_then(|provenance| provenance.value(field))
+            {
+                Some(value) => compile_regex(reg_expr).is_match(value),
+                None => false,
+            },
+            EntryFilter::All(filters) => filters.iter().all(|filter| filter.matches(entry)),
+            EntryFilter::Any(filters) => filters.iter().any(|filter| filter.matches(entry)),
+        }
+    }
+
+    /// Returns the regex patterns of all contained benchmark name filters. Benchmark filters
+    /// inside of `Any` only select whole entries.
+    fn benchmark_patterns(&self) -> Vec<&str> {
+        match self {
+            EntryFilter::Benchmark(reg_expr) => vec![reg_expr.as_str()],
+            EntryFilter::All(filters) => filters
+                .iter()
+                .flat_map(|filter| filter.benchmark_patterns())
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+/// Dates of results keep the local offset of the run, like "2021-03-01T12:00:00+01:00", or
+/// use older formats. Converted to "2021-03-01T11:00:00Z" they order like the points in
+/// time they describe. Values which aren't complete dates, e.g. prefixes like "2021-03" of
+/// filters, are returned as they are.
+pub fn utc_date(date: &str) -> String {
+    match parse_timestamp(date) {
+        Some(timestamp) => timestamp.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+        None => date.replacen(' ', "T", 1),
+    }
+}
+
+/// Appending a character sorting after all date characters makes "until" inclusive for
+/// every date prefix.
+pub fn until_bound(date: &str) -> String {
+    utc_date(date) + "~"
+}
+
+/// Decides what happens when pushing results of a run which is already stored.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum DuplicatePolicy {
+    Skip,
+    Replace,
+}
+
+/// Common interface of all backends which are able to store benchmark results.
+pub trait Storage {
+    /// Stores the given entries and returns the number of stored entries. Entries are
+    /// unique per run ID and executable, entries without run ID are always stored.
+    fn push(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) -> u64;
+
+    /// Returns all entries matching the filter ordered by date, optionally only the
+    /// `last` most recent ones.
+    fn fetch(&self, entry_filter: &EntryFilter, last: Option<usize>) -> Vec<DataBaseEntry>;
+
+    /// Deletes all entries matching the filter and returns the number of deleted entries.
+    fn delete(&self, entry_filter: &EntryFilter) -> u64;
+
+    /// Removes all but the first pushed entry of each run ID and executable (see
+    /// `entry_key`) and returns the number of removed entries.
+    fn remove_duplicates(&self) -> u64;
+
+    /// Returns the number of bytes used by the stored entries, if the backend knows it.
+    fn storage_size(&self) -> Option<u64>;
+
+    /// Calls `f` for every entry matching the filter in no particular order. Backends
+    /// which can stream their entries avoid holding the whole history in memory.
+    fn for_each_entry(&self, entry_filter: &EntryFilter, f: &mut dyn FnMut(DataBaseEntry)) {
+        for entry in self.fetch(entry_filter, None) {
+            f(entry);
+        }
+    }
+
+    /// Aggregates the cpu times of all benchmarks matching the filter per period and
+    /// returns the points ordered by period.
+    fn resample(&self, entry_filter: &EntryFilter, resampling: Resampling) -> Vec<ResampledPoint> {
+        let mut resampler = Resampler::new(resampling);
+        self.for_each_entry(entry_filter, &mut |entry| resampler.add_entry(&entry));
+        resampler.finish()
+    }
+}
+
+pub const STORAGE_BACKENDS: &[&str] = &["mongodb", "sqlite", "jsondir"];
+
+/// Opens the storage backend selected in the config, exits if it is unreachable.
+pub fn open_storage(config: &AppConfig) -> Box<dyn Storage> {
+    match try_open_storage(config) {
+        Ok(storage) => storage,
+        Err(e) => error_and_exit("Could not connect to database", e.as_ref()),
+    }
+}
+
+/// Opens the storage backend selected in the config. Like all backend diagnostics the
+/// progress is printed to stderr, so stdout can be used for e.g. json output.
+pub fn try_open_storage(config: &AppConfig) -> Result<Box<dyn Storage>, Box<dyn Error>> {
+    let storage: Box<dyn Storage> = match config.storage_backend() {
+        "mongodb" => Box::new(DataBase::init(config)?),
+        "sqlite" => Box::new(SqliteDataBase::init(config)?),
+        "jsondir" => Box::new(JsonDirDataBase::init(config)?),
+        backend => return Err(format!("Unknown storage backend '{}'", backend).into()),
+    };
+    Ok(storage)
+}
+
+/// Checks whether the configured backend is reachable and usable, prints the outcome.
+pub fn test_db_connection(config: &AppConfig) -> bool {
+    let result: Result<(), Box<dyn Error>> = match config.storage_backend() {
+        "mongodb" => return DataBase::test_connection(config),
+        "sqlite" => SqliteDataBase::init(config)
+            .map(|_| ())
+            .map_err(|e| e.into()),
+        "jsondir" => JsonDirDataBase::init(config)
+            .map(|_| ())
+            .map_err(|e| e.into()),
+        backend => {
+            println!("Unknown storage backend '{}'!", backend);
+            return false;
+        }
+    };
+    match result {
+        Ok(()) => {
+            println!(
+                "Opening {} storage ... {}",
+                config.storage_backend(),
+                "ok".green()
+            );
+            true
+        }
+        Err(e) => {
+            println!(
+                "Opening {} storage ... {}",
+                config.storage_backend(),
+                "failed".red()
+            );
+            println!("  {}", e);
+            false
+        }
+    }
+}
+
+/// Creates storable entries from the results of the last run including their provenance.
+pub fn entries_from_last_results(
+    tags: &[String],
+    meta: &BTreeMap<String, String>,
+) -> Vec<DataBaseEntry> {
+    entries_from_results(parse_cumulated_benchmark_file(), tags, meta)
+}
+
+/// Creates storable entries from benchmark results including their provenance. The
+/// provenance is the one collected when the benchmarks ran, results exported by previous
+/// versions don't have one and get the current provenance of their executable.
+pub fn entries_from_results(
+    results: Vec<BenchmarkResults>,
+    tags: &[String],
+    meta: &BTreeMap<String, String>,
+) -> Vec<DataBaseEntry> {
+    results
+        .into_iter()
+        .map(|mut results| {
+            let provenance = results.provenance.take().unwrap_or_else(|| {
+                Provenance::collect(&results.context.executable, results.run_id.clone())
+            });
+            DataBaseEntry {
+                exe_name: exe_name(&results.context.executable),
+                tags: tags.to_vec(),
+                meta: meta.clone(),
+                provenance: Some(provenance),
+                results,
+            }
+        })
+        .collect()
+}
+
+/// The run ID is part of the provenance and of the results of newer versions.
+pub fn run_id(entry: &DataBaseEntry) -> Option<&str> {
+    entry
+        .provenance
+        .as_ref()
+        .and_then(|provenance| provenance.run_id.as_deref())
+        .or(entry.results.run_id.as_deref())
+}
+
+/// Identifies an entry independent of the storage backend. Entries of the same run are
+/// distinguished by their executable, entries without run ID by their date.
+pub fn entry_key(entry: &DataBaseEntry) -> String {
+    match run_id(entry) {
+        Some(run_id) => format!("run:{}/{}", run_id, entry.exe_name),
+        None => format!("date:{}/{}", entry.results.context.date, entry.exe_name),
+    }
+}
+
+/// Removes all single benchmark results not matching the benchmark name filters. Backends
+/// select whole entries, so this is applied to the fetched entries afterwards.
+pub fn retain_matching_benchmarks(entries: &mut Vec<DataBaseEntry>, entry_filter: &EntryFilter) {
+    let patterns = benchmark_regexes(entry_filter);
+    entries.retain_mut(|entry| retain_benchmarks_of_entry(entry, &patterns));
+}
+
+/// Compiles the benchmark name patterns of the filter for `retain_benchmarks_of_entry`.
+pub fn benchmark_regexes(entry_filter: &EntryFilter) -> Vec<Regex> {
+    entry_filter
+        .benchmark_patterns()
+        .iter()
+        .map(|pattern| compile_regex(pattern))
+        .collect()
+}
+
+/// Single entry version of `retain_matching_benchmarks` for backends iterating over
+/// their entries, returns whether benchmarks are left.
+pub fn retain_benchmarks_of_entry(entry: &mut DataBaseEntry, patterns: &[Regex]) -> bool {
+    if patterns.is_empty() {
+        return true;
+    }
+    entry
+        .results
+        .benchmarks
+        .retain(|bm| patterns.iter().all(|re| re.is_match(&bm.name)));
+    !entry.results.benchmarks.is_empty()
+}
+
+/// Orders entries by date and keeps only the `last` most recent ones if requested.
+pub fn sort_and_limit(entries: &mut Vec<DataBaseEntry>, last: Option<usize>) {
+    entries.sort_by_cached_key(|entry| utc_date(&entry.results.context.date));
+    if let Some(last) = last {
+        let skipped = entries.len().saturating_sub(last);
+        entries.drain(..skipped);
+    }
+}
+
+/// Counts how many entries use each of the given names, e.g. tags or metadata keys.
+pub fn count_names<'a, I: Iterator<Item = &'a String>>(names: I) -> BTreeMap<String, u64> {
+    let mut counts = BTreeMap::new();
+    for name in names {
+        *counts.entry(name.clone()).or_insert(0) += 1;
+    }
+    counts
+}
+
+pub fn compile_regex(reg_expr: &str) -> Regex {
+    Regex::new(reg_expr).unwrap_or_else(|_| panic!("Could not compile regex: {}", reg_expr))
+}
+
+fn exe_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap()
+        .to_str()
+        .expect("Could not convert executable path to valid string!")
+        .to_string()
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::path::PathBuf;
+
+    /// Storage keeping all entries in memory, e.g. for testing without a database server.
+    #[derive(Default)]
+    pub struct MemoryStorage {
+        entries: RefCell<Vec<DataBaseEntry>>,
+    }
+
+    impl Storage for MemoryStorage {
+        fn push(&self, entries: &[DataBaseEntry], duplicates: DuplicatePolicy) -> u64 {
+            let mut stored_entries = self.entries.borrow_mut();
+            let mut stored_count = 0;
+            for entry in entries {
+                if let Some(run_id) = run_id(entry) {
+                    let is_same_run = |stored: &DataBaseEntry| {
+                        stored.exe_name == entry.exe_name && super::run_id(stored) == Some(run_id)
+                    };
+                    if stored_entries.iter().any(is_same_run) {
+                        if duplicates == DuplicatePolicy::Skip {
+                            continue;
+                        }
+                        stored_entries.retain(|stored| !is_same_run(stored));
+                    }
+                }
+                stored_entries.push(entry.clone());
+                stored_count += 1;
+            }
+            stored_count
+        }
+
+        fn fetch(&self, entry_filter: &EntryFilter, last: Option<usize>) -> Vec<DataBaseEntry> {
+            let mut entries = self
+                .entries
+                .borrow()
+                .iter()
+                .filter(|entry| entry_filter.matches(entry))
+                .cloned()
+                .collect();
+            retain_matching_benchmarks(&mut entries, entry_filter);
+            sort_and_limit(&mut entries, last);
+            entries
+        }
+
+        fn delete(&self, entry_filter: &EntryFilter) -> u64 {
+            let mut entries = self.entries.borrow_mut();
+            let count_before = entries.len();
+            entries.retain(|entry| !entry_filter.matches(entry));
+            (count_before - entries.len()) as u64
+        }
+
+        fn storage_size(&self) -> Option<u64> {
+            None
+        }
+
+        fn remove_duplicates(&self) -> u64 {
+            let mut entries = self.entries.borrow_mut();
+            let count_before = entries.len();
+            let mut keys = HashSet::new();
+            entries.retain(|entry| keys.insert(entry_key(entry)));
+            (count_before - entries.len()) as u64
+        }
+    }
+
+    pub fn test_entry(exe_name: &str, tag: &str) -> DataBaseEntry {
+        DataBaseEntry {
+            exe_name: exe_name.to_string(),
+            tags: if tag.is_empty() {
+                vec![]
+            } else {
+                vec![tag.to_string()]
+            },
+            meta: BTreeMap::new(),
+            provenance: None,
+            results: BenchmarkResults {
+                commit: None,
+                run_id: None,
+                provenance: None,
+                context: BenchmarkContext {
+                    date: "2021-01-01T00:00:00+00:00".to_string(),
+                    executable: PathBuf::from("/tmp").join(exe_name),
+                    num_cpus: 4,
+                    mhz_per_cpu: 3000,
+                    host_name: None,
+                    library_build_type: None,
+                },
+                benchmarks: vec![
+                    BenchmarkResult {
+                        name: "BM_Foo".to_string(),
+                        iterations: 1,
+                        real_time: 1.0,
+                        cpu_time: 1.0,
+                        time_unit: Some("ns".to_string()),
+                    },
+                    BenchmarkResult {
+                        name: "BM_Bar".to_string(),
+                        iterations: 1,
+                        real_time: 1.0,
+                        cpu_time: 1.0,
+                        time_unit: Some("ns".to_string()),
+                    },
+                ],
+            },
+        }
+    }
+
+    #[test]
+    fn test_memory_storage() {
+        let storage: Box<dyn Storage> = Box::new(MemoryStorage::default());
+        storage.push(
+            &[
+                test_entry("simple_benchmark", "v1"),
+                test_entry("with_arg_list_benchmark", ""),
+            ],
+            DuplicatePolicy::Skip,
+        );
+
+        let fetched = storage.fetch(&EntryFilter::ExeName("^simple.*".to_string()), None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].exe_name, "simple_benchmark");
+        assert_eq!(fetched[0].tags, vec!["v1"]);
+
+        assert_eq!(storage.delete(&EntryFilter::ExeName(".*".to_string())), 2);
+        assert!(storage
+            .fetch(&EntryFilter::ExeName(".*".to_string()), None)
+            .is_empty());
+    }
+
+    #[test]
+    fn test_combined_entry_filter() {
+        let mut old_entry = test_entry("simple_benchmark", "v1");
+        old_entry.results.context.date = "2020-06-15T10:00:00+02:00".to_string();
+        let new_entry = test_entry("simple_benchmark", "v2");
+
+        let storage = MemoryStorage::default();
+        storage.push(&[old_entry, new_entry], DuplicatePolicy::Skip);
+
+        let since = EntryFilter::All(vec![
+            EntryFilter::ExeName("simple".to_string()),
+            EntryFilter::Since("2020-12".to_string()),
+        ]);
+        assert_eq!(storage.fetch(&since, None)[0].tags, vec!["v2"]);
+
+        let until = EntryFilter::Until("2020-06-15".to_string());
+        assert_eq!(storage.fetch(&until, None)[0].tags, vec!["v1"]);
+
+        let tag_regex = EntryFilter::TagRegex("^v".to_string());
+        let last = storage.fetch(&tag_regex, Some(1));
+        assert_eq!(last.len(), 1);
+        assert_eq!(last[0].tags, vec!["v2"]);
+
+        let benchmark = EntryFilter::All(vec![
+            EntryFilter::Tag("v1".to_string()),
+            EntryFilter::Benchmark("Foo".to_string()),
+        ]);
+        let fetched = storage.fetch(&benchmark, None);
+        assert_eq!(fetched.len(), 1);
+        assert_eq!(fetched[0].results.benchmarks.len(), 1);
+        assert_eq!(fetched[0].results.benchmarks[0].name, "BM_Foo");
+    }
+
+    /// Entries in the order of their dates in UTC, although their date strings order differently.
+    pub fn entries_with_offsets() -> Vec<DataBaseEntry> {
+        [
+            ("local", "01/01/21 12:00:00"),
+            ("east", "2021-01-02T01:00:00+02:00"),
+            ("west", "2021-01-01T20:00:00-06:00"),
+        ]
+        .iter()
+        .map(|(tag, date)| {
+            let mut entry = test_entry("simple_benchmark", tag);
+            entry.results.context.date = date.to_string();
+            entry
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_date_filters_in_utc() {
+        assert_eq!(
+            utc_date("2021-01-02T01:00:00+02:00"),
+            "2021-01-01T23:00:00Z"
+        );
+        assert_eq!(utc_date("01/01/21 12:00:00"), "2021-01-01T12:00:00Z");
+        assert_eq!(utc_date("2021-01"), "2021-01");
+
+        let storage = MemoryStorage::default();
+        let mut entries = entries_with_offsets();
+        entries.reverse();
+        storage.push(&entries, DuplicatePolicy::Skip);
+
+        let tags = |entries: Vec<DataBaseEntry>| -> Vec<String> {
+            entries.into_iter().flat_map(|entry| entry.tags).collect()
+        };
+        let all = EntryFilter::All(vec![]);
+        assert_eq!(
+            tags(storage.fetch(&all, None)),
+            vec!["local", "east", "west"]
+        );
+        assert_eq!(tags(storage.fetch(&all, Some(1))), vec!["west"]);
+
+        let since = EntryFilter::Since("2021-01-02".to_string());
+        assert_eq!(tags(storage.fetch(&since, None)), vec!["west"]);
+        let until = EntryFilter::Until("2021-01-01".to_string());
+        assert_eq!(tags(storage.fetch(&until, None)), vec!["local", "east"]);
+        let before = EntryFilter::Before("2021-01-02T00:00:00+01:00".to_string());
+        assert_eq!(tags(storage.fetch(&before, None)), vec!["local"]);
+    }
+
+    #[test]
+    fn test_entries_use_provenance_of_the_run() {
+        let mut results = test_entry("simple_benchmark", "").results;
+        results.provenance = Some(Provenance {
+            git_commit: Some("abc123".to_string()),
+            ..Default::default()
+        });
+        let entries = entries_from_results(vec![results], &[], &BTreeMap::new());
+        let provenance = entries[0].provenance.as_ref().unwrap();
+        assert_eq!(provenance.git_commit.as_deref(), Some("abc123"));
+        // stored once with the entry
+        assert!(entries[0].results.provenance.is_none());
+    }
+
+    #[test]
+    fn test_any_entry_filter_selects_union() {
+        let storage = MemoryStorage::default();
+        storage.push(&entries_with_offsets(), DuplicatePolicy::Skip);
+
+        let overlapping = EntryFilter::Any(vec![
+            EntryFilter::Tag("east".to_string()),
+            EntryFilter::TagRegex("^(east|west)$".to_string()),
+        ]);
+        assert_eq!(storage.fetch(&overlapping, None).len(), 2);
+        assert!(storage.fetch(&EntryFilter::Any(vec![]), None).is_empty());
+        assert_eq!(storage.delete(&overlapping), 2);
+        assert_eq!(storage.fetch(&EntryFilter::All(vec![]), None).len(), 1);
+    }
+
+    #[test]
+    fn test_provenance_entry_filter() {
+        let mut entry = test_entry("simple_benchmark", "");
+        entry.provenance = Some(Provenance {
+            git_branch: Some("master".to_string()),
+            host_name: Some("buildhost".to_string()),
+            ..Default::default()
+        });
+
+        let storage = MemoryStorage::default();
+        storage.push(
+            &[entry, test_entry("simple_benchmark", "")],
+            DuplicatePolicy::Skip,
+        );
+
+        let branch = EntryFilter::Provenance(ProvenanceField::GitBranch, "^master$".to_string());
+        assert_eq!(storage.fetch(&branch, None).len(), 1);
+        let host = EntryFilter::Host("build".to_string());
+        assert_eq!(storage.fetch(&host, None).len(), 1);
+    }
+
+    #[test]
+    fn test_duplicate_runs() {
+        let mut entry = test_entry("simple_benchmark", "v1");
+        entry.results.run_id = Some("run1".to_string());
+        let mut legacy_duplicate = test_entry("with_arg_list_benchmark", "");
+        legacy_duplicate.results.context.date = "2020-06-15T10:00:00+02:00".to_string();
+
+        let storage = MemoryStorage::default();
+        let entries = [entry.clone(), legacy_duplicate.clone(), legacy_duplicate];
+        assert_eq!(storage.push(&entries, DuplicatePolicy::Skip), 3);
+        assert_eq!(storage.push(&[entry.clone()], DuplicatePolicy::Skip), 0);
+
+        entry.tags = vec!["v2".to_string()];
+        assert_eq!(storage.push(&[entry], DuplicatePolicy::Replace), 1);
+        let tagged = storage.fetch(&EntryFilter::TagRegex(".".to_string()), None);
+        assert_eq!(tagged.len(), 1);
+        assert_eq!(tagged[0].tags, vec!["v2"]);
+
+        assert_eq!(storage.remove_duplicates(), 1);
+        assert_eq!(storage.fetch(&EntryFilter::All(vec![]), None).len(), 2);
+    }
+}